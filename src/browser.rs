@@ -0,0 +1,238 @@
+use crate::locate::{ItemIndex, ItemKind};
+
+use std::collections::HashMap;
+
+use tui::{
+    style::{Modifier, Style},
+    text::Span,
+    widgets::{ListItem, ListState},
+};
+
+// An item the segment pane can be switched to
+pub struct Entry {
+    pub module: String,
+    pub kind: ItemKind,
+    pub title: String,
+    // identifies the entry across expansions, same named items like the `const _` of derives
+    // get their position among them, `const _ in crate #2`
+    pub label: String,
+    pub code: String,
+}
+
+// A line of the tree, the index points into the entries
+enum Row {
+    Module(usize),
+    Kind(usize),
+    Item(usize),
+}
+
+// Tree of every item in the expanded crate, grouped by module then kind
+pub struct Browser {
+    entries: Vec<Entry>,
+    rows: Vec<Row>,
+    pub filter: String,
+    pub filtering: bool,
+    selected: Option<usize>,
+    // label of the item open in the segment pane
+    current: Option<String>,
+}
+
+impl Browser {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            rows: Vec::new(),
+            filter: String::new(),
+            filtering: false,
+            selected: None,
+            current: None,
+        }
+    }
+
+    // replace the entries with the ones of a new expansion, keeping the selection if the
+    // item is still there
+    pub fn update(&mut self, entries: Vec<Entry>) {
+        let selected = self.selected_label();
+
+        self.entries = entries;
+        self.entries
+            .sort_by(|a, b| (&a.module, a.kind, &a.title).cmp(&(&b.module, b.kind, &b.title)));
+
+        self.rebuild(selected);
+    }
+
+    // the selection jumps to the best match while typing
+    pub fn push(&mut self, c: char) {
+        self.filter.push(c);
+        self.rebuild(None);
+    }
+
+    pub fn pop(&mut self) {
+        self.filter.pop();
+        self.rebuild(None);
+    }
+
+    pub fn clear_filter(&mut self) {
+        let selected = self.selected_label();
+        self.filter.clear();
+        self.filtering = false;
+        self.rebuild(selected);
+    }
+
+    pub fn select_next(&mut self) {
+        let selected = self.selected;
+        let next = self
+            .item_rows()
+            .find(|row| selected.is_none_or(|selected| *row > selected));
+        if next.is_some() {
+            self.selected = next;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        let selected = self.selected.unwrap_or(usize::MAX);
+        let prev = self.item_rows().rev().find(|row| *row < selected);
+        if prev.is_some() {
+            self.selected = prev;
+        }
+    }
+
+    // make the selected item the one shown in the segment pane
+    pub fn open(&mut self) -> Option<&Entry> {
+        let label = self.selected_entry()?.label.clone();
+        self.current = Some(label);
+        self.current_entry()
+    }
+
+    // the item open in the segment pane, if it is still in the expansion
+    pub fn current_entry(&self) -> Option<&Entry> {
+        let current = self.current.as_ref()?;
+        self.entries.iter().find(|entry| &entry.label == current)
+    }
+
+    pub fn items(&self) -> Vec<ListItem<'_>> {
+        self.rows
+            .iter()
+            .map(|row| match *row {
+                Row::Module(index) => ListItem::new(Span::styled(
+                    self.entries[index].module.as_str(),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )),
+                Row::Kind(index) => ListItem::new(format!("  {}", self.entries[index].kind)),
+                Row::Item(index) => ListItem::new(format!("    {}", self.entries[index].title)),
+            })
+            .collect()
+    }
+
+    pub fn state(&self) -> ListState {
+        let mut state = ListState::default();
+        state.select(self.selected);
+        state
+    }
+
+    fn selected_entry(&self) -> Option<&Entry> {
+        match self.rows.get(self.selected?)? {
+            Row::Item(index) => self.entries.get(*index),
+            _ => None,
+        }
+    }
+
+    fn selected_label(&self) -> Option<String> {
+        self.selected_entry().map(|entry| entry.label.clone())
+    }
+
+    fn select_label(&mut self, label: &str) {
+        let row = self
+            .rows
+            .iter()
+            .position(|row| matches!(row, Row::Item(index) if self.entries[*index].label == label));
+        self.selected = row.or(self.selected);
+    }
+
+    fn item_rows(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| matches!(row, Row::Item(_)))
+            .map(|(index, _)| index)
+    }
+
+    // lay the matching entries out as a tree, entries are sorted by module and kind already
+    fn rebuild(&mut self, selected: Option<String>) {
+        let mut last: Option<&Entry> = None;
+
+        self.rows.clear();
+        for (index, entry) in self.entries.iter().enumerate() {
+            let text = format!("{} {} {}", entry.module, entry.kind, entry.title);
+            if !fuzzy_match(&self.filter, &text) {
+                continue;
+            }
+
+            let new_module = last.is_none_or(|last| last.module != entry.module);
+            if new_module {
+                self.rows.push(Row::Module(index));
+            }
+            if new_module || last.is_some_and(|last| last.kind != entry.kind) {
+                self.rows.push(Row::Kind(index));
+            }
+            self.rows.push(Row::Item(index));
+            last = Some(entry);
+        }
+
+        self.selected = None;
+        if let Some(label) = selected {
+            self.select_label(&label);
+        }
+        // an item with the filter in its name beats one that only matches fuzzily
+        if self.selected.is_none() {
+            let filter = self.filter.to_lowercase();
+            let best = self
+                .item_rows()
+                .find(|row| match self.rows[*row] {
+                    Row::Item(index) => self.entries[index].title.to_lowercase().contains(&filter),
+                    _ => false,
+                })
+                .or_else(|| self.item_rows().next());
+            self.selected = best;
+        }
+    }
+}
+
+// the items of an expansion, the index itself cannot leave the thread that parsed it
+pub fn entries(index: &ItemIndex) -> Vec<Entry> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    index
+        .items()
+        // methods are listed with their impl
+        .filter(|item| item.kind != ItemKind::Module && item.owner.is_none())
+        .map(|item| {
+            let label = item.to_string();
+            let count = seen.entry(label.clone()).or_default();
+            *count += 1;
+
+            Entry {
+                module: item.module(),
+                kind: item.kind,
+                title: item.title(),
+                label: if *count > 1 {
+                    format!("{} #{}", label, count)
+                } else {
+                    label
+                },
+                code: item.code(),
+            }
+        })
+        .collect()
+}
+
+// every character of the filter has to appear in order, ignoring case
+fn fuzzy_match(filter: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+
+    filter
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+        .all(|c| text.any(|t| t == c))
+}
@@ -0,0 +1,249 @@
+use crate::metadata::Metadata;
+use crate::{error_other, Args};
+
+use std::cmp::Reverse;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use ignore::WalkBuilder;
+use serde_json::{json, Value};
+
+// Expansions kept around, older ones are removed
+const KEEP: usize = 8;
+
+// Env vars cargo turns into compiler flags or cfgs, by prefix
+const BUILD_ENV: [&str; 5] = [
+    "RUSTFLAGS",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "CARGO_BUILD_",
+    "CARGO_TARGET_",
+    "CARGO_PROFILE_",
+];
+
+// On-disk cache of expansions in target/croc-look, keyed by everything that can change what
+// cargo prints: the sources, Cargo.lock, the toolchain, cargo's config and the command itself
+pub struct Cache {
+    dir: PathBuf,
+    // source dirs and manifests of the current package, its path dependencies and the proc
+    // macro crates of the workspace
+    sources: Vec<PathBuf>,
+    lock_file: PathBuf,
+    toolchain: String,
+    // every .cargo/config(.toml) cargo could read, whether it is there or not
+    config_files: Vec<PathBuf>,
+}
+
+// FNV-1a, the keys are file names on disk so the hash cannot change between Rust releases the
+// way `DefaultHasher` may
+struct Fnv(u64);
+
+impl Cache {
+    // `rustc` is the compiler of the expander, its version goes into the key
+    pub fn new(args: &Args, mut rustc: Command) -> Result<Self> {
+        let metadata = Metadata::load(args)?;
+        let output = rustc.arg("-vV").output()?;
+
+        if !output.status.success() {
+            return Err(error_other(format!(
+                "Cannot get the toolchain version, stderr: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        Ok(Self {
+            dir: metadata.target_directory.join("croc-look"),
+            sources: metadata.watch_paths(),
+            lock_file: metadata.workspace_root.join("Cargo.lock"),
+            toolchain: String::from_utf8_lossy(&output.stdout).to_string(),
+            config_files: config_files(&metadata),
+        })
+    }
+
+    pub fn key(&self, cmd: &Command) -> String {
+        let mut hasher = Fnv::new();
+
+        hasher.field(self.toolchain.as_bytes());
+        hasher.field(cmd.get_program().as_encoded_bytes());
+        cmd.get_args()
+            .for_each(|arg| hasher.field(arg.as_encoded_bytes()));
+        for (name, value) in cmd.get_envs() {
+            hasher.field(name.as_encoded_bytes());
+            hasher.optional(value.map(OsStr::as_encoded_bytes));
+        }
+        for (name, value) in build_env() {
+            hasher.field(name.as_encoded_bytes());
+            hasher.field(value.as_encoded_bytes());
+        }
+        hasher.file(&self.lock_file);
+        self.config_files.iter().for_each(|path| hasher.file(path));
+
+        for source in &self.sources {
+            let files = WalkBuilder::new(source)
+                .sort_by_file_path(|a, b| a.cmp(b))
+                .build()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_some_and(|e| e.is_file()));
+
+            for file in files {
+                hasher.field(file.path().as_os_str().as_encoded_bytes());
+                hasher.file(file.path());
+            }
+        }
+
+        format!("{:016x}", hasher.0)
+    }
+
+    // the expanded code and macro output stored under the key
+    pub fn get(&self, key: &str) -> Option<(String, String)> {
+        let json: Value = serde_json::from_slice(&fs::read(self.path(key)).ok()?).ok()?;
+
+        Some((
+            json["code"].as_str()?.to_string(),
+            json["macro_output"].as_str()?.to_string(),
+        ))
+    }
+
+    // a cache that cannot be written to is only slower, so failing is fine
+    pub fn put(&self, key: &str, code: &str, macro_output: &str) {
+        let json = json!({ "code": code, "macro_output": macro_output });
+
+        if fs::create_dir_all(&self.dir).is_ok()
+            && fs::write(self.path(key), json.to_string()).is_ok()
+        {
+            self.evict();
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    // only the most recent expansions are worth keeping
+    fn evict(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut entries = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect::<Vec<_>>();
+
+        entries.sort_by_key(|(modified, _)| Reverse(*modified));
+        for (_, path) in entries.into_iter().skip(KEEP) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Fnv {
+    const fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    // length first, so one field cannot run into the next
+    fn field(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+
+    fn optional(&mut self, bytes: Option<&[u8]>) {
+        self.write(&[u8::from(bytes.is_some())]);
+        if let Some(bytes) = bytes {
+            self.field(bytes);
+        }
+    }
+
+    // a missing file hashes differently from an empty one
+    fn file(&mut self, path: &Path) {
+        self.optional(fs::read(path).ok().as_deref());
+    }
+}
+
+// the env vars of BUILD_ENV that are set, sorted so the order of the environment does not matter
+fn build_env() -> Vec<(OsString, OsString)> {
+    let mut vars = env::vars_os()
+        .filter(|(name, _)| {
+            name.to_str()
+                .is_some_and(|name| BUILD_ENV.iter().any(|prefix| name.starts_with(prefix)))
+        })
+        .collect::<Vec<_>>();
+    vars.sort();
+    vars
+}
+
+// cargo reads .cargo/config.toml (or the older .cargo/config) in the current dir and every dir
+// above it, then in CARGO_HOME. The package dir is searched too, for --manifest-path
+fn config_files(metadata: &Metadata) -> Vec<PathBuf> {
+    let package_dir = metadata
+        .current()
+        .first()
+        .map(|package| package.dir().to_path_buf());
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+
+    let mut dirs = Vec::new();
+    for start in env::current_dir().ok().into_iter().chain(package_dir) {
+        for dir in start.ancestors().map(|dir| dir.join(".cargo")) {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs.extend(cargo_home.filter(|home| !dirs.contains(home)));
+
+    dirs.iter()
+        .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv_is_fixed() {
+        let mut hasher = Fnv::new();
+        hasher.write(b"foobar");
+
+        // the published FNV-1a 64 test vector
+        assert_eq!(hasher.0, 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn key_follows_cargo_config() {
+        let dir = env::temp_dir().join(format!("croc-look-cache-{}", std::process::id()));
+        let config = dir.join(".cargo").join("config.toml");
+        let cache = Cache {
+            dir: dir.clone(),
+            sources: Vec::new(),
+            lock_file: dir.join("Cargo.lock"),
+            toolchain: "rustc 1.0.0".to_string(),
+            config_files: vec![config.clone()],
+        };
+        let cmd = Command::new("cargo");
+
+        let missing = cache.key(&cmd);
+        fs::create_dir_all(config.parent().unwrap()).unwrap();
+        fs::write(&config, "").unwrap();
+        let empty = cache.key(&cmd);
+        fs::write(&config, "[build]\nrustflags = [\"--cfg\", \"my_cfg\"]\n").unwrap();
+        let rustflags = cache.key(&cmd);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_ne!(missing, empty);
+        assert_ne!(empty, rustflags);
+        assert_eq!(cache.key(&cmd), missing);
+    }
+}
@@ -1,14 +1,25 @@
-use crate::locate::{loc_function, loc_struct, loc_trait_impl};
+use crate::cache::Cache;
+use crate::expander::{self, Expander};
+use crate::format::Formatter;
+use crate::locate::{AssocSelector, IndexedItem, ItemIndex, ItemKind};
+use crate::parse::{self, ParseError};
+use crate::query::{Query, Target};
+use crate::worker::Expansion;
 use crate::{error_other, Args};
 
 use std::io::{Error, ErrorKind, Result};
-use std::process::{Command, Stdio};
+use std::process::Child;
+use std::sync::{Arc, Mutex, OnceLock};
 
 use crossbeam::channel::{unbounded, Receiver, Sender};
+use quote::ToTokens;
+use syn::Type;
 
 // Interrupt channel
 pub type ChannelSender = Sender<Event>;
 pub type Channel = (ChannelSender, Receiver<Event>);
+// Cargo process of the expansion that is running, taken out to cancel it
+pub type Running = Arc<Mutex<Option<Child>>>;
 
 pub enum Event {
     Interrupt,
@@ -18,19 +29,48 @@ pub enum Event {
     KeyArrowDown,
     KeyArrowRight,
     KeyArrowLeft,
+    KeyTab,
+    KeyEnter,
+    KeyEsc,
+    KeyBackspace,
+    KeyChar(char),
+    Tick,
+    ExpansionStarted,
+    ExpansionFinished(Result<Expansion>),
 }
 
 #[derive(Clone)]
 pub struct Context {
     pub args: Args,
     pub main_channel: Channel,
+    pub running: Running,
+    formatter: Formatter,
+    // where the expanded source comes from
+    expander: Arc<dyn Expander>,
+    // set up on the first expansion, `None` with --no-cache or if there is nowhere to put it
+    cache: Arc<OnceLock<Option<Cache>>>,
 }
 
 impl Context {
-    pub fn new(args: Args) -> Self {
-        let main_channel = unbounded();
+    // reads stdin right away with `--input -`
+    pub fn new(args: Args) -> Result<Self> {
+        let expander = expander::from_args(&args)?;
 
-        Self { args, main_channel }
+        Ok(Self::with_expander(args, expander))
+    }
+
+    // expands with `expander` whatever the flags say, a stand-in needs no toolchain
+    pub fn with_expander(args: Args, expander: Box<dyn Expander>) -> Self {
+        let formatter = Formatter::new(&args);
+
+        Self {
+            args,
+            main_channel: unbounded(),
+            running: Arc::default(),
+            formatter,
+            expander: Arc::from(expander),
+            cache: Arc::default(),
+        }
     }
 
     // helper function to send events on channel
@@ -41,102 +81,128 @@ impl Context {
             .map_err(|e| error_other(format!("Cannot send event: {}", e)))
     }
 
-    // to format code using rustfmt (requires rustfmt to be installed)
-    pub fn format_code(&self, code: &str) -> Result<String> {
-        let cmd = Command::new("echo")
-            .arg(code)
-            .stdout(Stdio::piped())
-            .spawn()?;
-
-        if let Some(stdout) = cmd.stdout {
-            let res = match Command::new("rustfmt").stdin(stdout).output() {
-                Ok(e) if e.status.success() => {
-                    return Ok(String::from_utf8_lossy(&e.stdout).to_string());
-                }
-                Ok(e) => Err(error_other(format!(
-                    "Cannot format code, stdout: {}, stderr: {}",
-                    String::from_utf8_lossy(&e.stdout),
-                    String::from_utf8_lossy(&e.stderr)
-                ))),
-                Err(e) => Err(error_other(format!("Cannot format code: {}", e))),
-            };
-
-            // failed since not returned
-            self.send(Event::Interrupt)?;
-            res
-        } else {
-            self.send(Event::Interrupt)?;
-            Err(error_other("Cannot read stdout".to_string()))
+    // with prettyplease or rustfmt, as picked with --formatter. Unformatted beats nothing, so
+    // code the formatter cannot print is shown as it is, on a line of its own like formatted code
+    pub fn format_code(&self, code: String) -> String {
+        self.formatter
+            .format(&code)
+            .unwrap_or_else(|_| format!("{}\n", code))
+    }
+
+    // kill the running expansion, it fails with an `Interrupted` error once all of cargo is gone
+    pub fn cancel(&self) {
+        if let Ok(mut running) = self.running.lock() {
+            if let Some(child) = running.take() {
+                expander::kill(child);
+            }
         }
     }
 
-    pub fn c_trait(&self, ident: &str, impl_for: Option<&str>) -> Result<String> {
-        let code = expand(&self.args)?;
-        loc_trait_impl(ident, syn::parse_str(&code).unwrap(), impl_for)
-            .as_ref()
-            .map(|e| self.format_code(e))
-            .ok_or_else(|| Error::from(ErrorKind::NotFound))?
+    // expand the crate and index every item in it, along with what the proc macros printed
+    // and, with --recover, why the items left out do not parse
+    pub fn index(&self) -> Result<(ItemIndex, String, Vec<ParseError>)> {
+        let (code, macro_output) = self.expander.expand(self)?;
+        let (file, skipped) = if self.args.recover {
+            parse::recover(&code)
+        } else {
+            (parse::parse(&code)?, Vec::new())
+        };
+
+        Ok((ItemIndex::new(&file), macro_output, skipped))
     }
 
-    pub fn c_struct(&self, ident: &str) -> Result<String> {
-        let code = expand(&self.args)?;
-        loc_struct(ident, syn::parse_str(&code).unwrap())
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache
+            .get_or_init(|| {
+                if self.args.no_cache {
+                    None
+                } else {
+                    Cache::new(&self.args, self.expander.rustc()).ok()
+                }
+            })
             .as_ref()
-            .map(|e| self.format_code(e))
-            .ok_or_else(|| Error::from(ErrorKind::NotFound))?
     }
 
-    pub fn c_func(&self, ident: &str) -> Result<String> {
-        let code = expand(&self.args)?;
-        loc_function(ident, syn::parse_str(&code).unwrap())
-            .as_ref()
-            .map(|e| self.format_code(e))
-            .ok_or_else(|| Error::from(ErrorKind::NotFound))?
+    pub fn c_trait(
+        &self,
+        index: &ItemIndex,
+        ident: &str,
+        impl_for: Option<&str>,
+    ) -> Result<String> {
+        let impl_for = impl_for.map(parse_type).transpose()?;
+        let item = index.trait_impls(ident, impl_for.as_ref()).next();
+
+        self.format_item(item)
     }
-}
 
-// requires nightly
-pub fn expand(args: &Args) -> Result<String> {
-    let mut cmd;
-
-    cmd = Command::new("rustup");
-    if let Some(binary) = &args.binary {
-        cmd.arg("run")
-            .arg("nightly")
-            .arg("cargo")
-            .arg("rustc")
-            .arg("--bin")
-            .arg(binary)
-            .arg("--profile=check")
-            .arg("--")
-            .arg("-Zunpretty=expanded");
-    } else {
-        cmd = Command::new("rustup");
-        cmd.arg("run")
-            .arg("nightly")
-            .arg("cargo")
-            .arg("rustc")
-            .arg("--lib")
-            .arg("--profile=check")
-            .arg("--")
-            .arg("-Zunpretty=expanded");
+    // every impl of the trait, each one labelled with its self type and module
+    pub fn c_trait_all(
+        &self,
+        index: &ItemIndex,
+        ident: &str,
+        impl_for: Option<&str>,
+    ) -> Result<String> {
+        let impl_for = impl_for.map(parse_type).transpose()?;
+
+        self.format_labelled(index.trait_impls(ident, impl_for.as_ref()))
     }
 
-    if let Some(path) = &args.path {
-        cmd = Command::new("cargo");
-        cmd.arg("expand").arg(path);
+    // every inherent impl block of the type
+    pub fn c_inherent(&self, index: &ItemIndex, ty: &str) -> Result<String> {
+        let ty = parse_type(ty)?;
+
+        self.format_labelled(index.inherent_impls(&ty))
     }
 
-    let output = cmd.output()?;
-
-    if output.status.success() {
-        Ok(String::from_utf8_lossy(&output.stdout).to_string())
-    } else {
-        // vomit stdout and stderr if it fails
-        Err(error_other(format!(
-            "Cannot expand code, stdout: {}, stderr: {}",
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
-        )))
+    // a single associated item, `Foo::build` or `<Foo as Trait>::method`
+    pub fn c_method(&self, index: &ItemIndex, selector: &str) -> Result<String> {
+        let selector: AssocSelector = syn::parse_str(selector)
+            .map_err(|e| error_other(format!("Invalid method selector `{}`: {}", selector, e)))?;
+
+        index
+            .associated_item(&selector)
+            .map(|(_, item)| self.format_code(item.to_token_stream().to_string()))
+            .ok_or_else(|| Error::from(ErrorKind::NotFound))
     }
+
+    pub fn c_item(&self, index: &ItemIndex, kind: ItemKind, ident: &str) -> Result<String> {
+        let item = index.find(kind, ident).next();
+
+        self.format_item(item)
+    }
+
+    // every item matching the query, methods are looked up like --method
+    pub fn c_query(&self, index: &ItemIndex, query: &Query) -> Result<String> {
+        if let Target::Method(selector) = &query.target {
+            return self.c_method(index, selector);
+        }
+
+        self.format_labelled(index.items().filter(|item| query.matches(item)))
+    }
+
+    fn format_item(&self, item: Option<&IndexedItem>) -> Result<String> {
+        item.map(|e| self.format_code(e.code()))
+            .ok_or_else(|| Error::from(ErrorKind::NotFound))
+    }
+
+    // format each item with a comment saying what it is and where it lives
+    fn format_labelled<'a>(&self, items: impl Iterator<Item = &'a IndexedItem>) -> Result<String> {
+        let mut blocks = Vec::new();
+
+        for item in items {
+            blocks.push(format!("// {}\n{}", item, self.format_code(item.code())));
+        }
+
+        if blocks.is_empty() {
+            return Err(Error::from(ErrorKind::NotFound));
+        }
+
+        Ok(blocks.join("\n"))
+    }
+}
+
+// type pattern for --impl-for, `_` stands for any type
+fn parse_type(pattern: &str) -> Result<Type> {
+    syn::parse_str(pattern)
+        .map_err(|e| error_other(format!("Invalid type pattern `{}`: {}", pattern, e)))
 }
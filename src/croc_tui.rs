@@ -1,21 +1,70 @@
-use std::time::Instant;
+use crate::browser::Browser;
+use crate::diagnostics;
+use crate::diff::{self, DiffMode};
+use crate::highlight::Highlighter;
+
+use std::io::Error;
+use std::time::{Duration, Instant};
 
 use tui::{
     backend::Backend,
-    layout::{Alignment, Constraint, Direction, Layout},
-    style::{Color, Style},
-    widgets::{Block, Borders, Paragraph, Wrap},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans, Text},
+    widgets::{Block, Borders, Clear, List, Paragraph, Wrap},
     Frame,
 };
 
-type Components<'a> = (Paragraph<'a>, Paragraph<'a>, Paragraph<'a>);
+// the fifth paragraph is the current expansion when diffing side by side, the sixth the
+// errors of a failed expansion and the last one what the proc macros printed
+type Components<'a> = (
+    Paragraph<'a>,
+    Paragraph<'a>,
+    Paragraph<'a>,
+    List<'a>,
+    Option<Paragraph<'a>>,
+    Option<Paragraph<'a>>,
+    Option<Paragraph<'a>>,
+);
 
 // Handle TUI components
 pub struct CrocTui {
     layout: Layout,
+    body: Layout,
     pub code_block: String,
+    // syntax highlighted code block, `None` if colours are turned off
+    highlighter: Option<Highlighter>,
+    highlighted: Option<Text<'static>>,
+    // expansion before the last change to the code block
+    previous: Option<String>,
+    pub diff: DiffMode,
     pub expanding: String,
     pub scroll: Scroll,
+    pub browser: Browser,
+    pub focus: Focus,
+    // when the expansion running in the background started
+    pub running: Option<Instant>,
+    // how long the last expansion took
+    pub took: Option<Duration>,
+    // items of the last expansion left out by --recover as they do not parse
+    pub skipped: usize,
+    // why the last expansion failed, the code block keeps the last good one
+    failure: Option<(String, Text<'static>)>,
+    pub error_scroll: Scroll,
+    // stderr of the proc macros, their debug prints
+    macro_output: String,
+    pub output_scroll: Scroll,
+}
+
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+// Pane that receives the arrow keys
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    Segment,
+    Browser,
+    Output,
+    Errors,
 }
 
 // Handle scrolling
@@ -27,7 +76,7 @@ pub struct Scroll {
 }
 
 impl CrocTui {
-    pub fn new(code_block: String, expanding: String) -> Self {
+    pub fn new(code_block: String, expanding: String, highlighter: Option<Highlighter>) -> Self {
         let layout = Layout::default()
             .direction(Direction::Vertical)
             .margin(1)
@@ -40,67 +89,351 @@ impl CrocTui {
                 .as_ref(),
             );
 
-        let mut lines = 0;
-        let mut line_array = Vec::new();
-
-        code_block.lines().for_each(|e| {
-            line_array.push(e.len() - 1);
-            lines += 1;
-        });
+        let body = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)].as_ref());
 
-        let scroll = Scroll::new(lines, line_array.iter().max().copied().unwrap_or(0));
+        let scroll = Scroll::for_code(&code_block);
+        let highlighted = highlighter
+            .as_ref()
+            .and_then(|e| e.highlight(&code_block).ok());
 
         Self {
             layout,
+            body,
             code_block,
+            highlighter,
+            highlighted,
+            previous: None,
+            diff: DiffMode::Off,
             expanding,
             scroll,
+            browser: Browser::new(),
+            focus: Focus::Segment,
+            running: None,
+            took: None,
+            skipped: 0,
+            failure: None,
+            macro_output: String::new(),
+            output_scroll: Scroll::new(0, 0),
+            error_scroll: Scroll::new(0, 0),
         }
     }
 
+    // new expansion of the same item, the old one is kept around for diffing
     pub fn code_block(&mut self, code_block: String) -> &mut Self {
+        if code_block != self.code_block {
+            self.previous = Some(std::mem::replace(&mut self.code_block, code_block));
+            self.highlight();
+            self.refresh_scroll();
+        }
+        self
+    }
+
+    // show a different item, scrolling starts over
+    pub fn open(&mut self, code_block: String, expanding: String) -> &mut Self {
+        self.scroll = Scroll::for_code(&code_block);
         self.code_block = code_block;
+        self.previous = None;
+        self.expanding = expanding;
+        self.highlight();
         self
     }
 
-    pub fn components(&self, now: Instant) -> (Paragraph, Paragraph, Paragraph) {
+    // keep showing the last good expansion with the errors on top of it
+    pub fn failed(&mut self, err: &Error) {
+        let title = if diagnostics::compile_error(err).is_some() {
+            "Compile errors, showing the last good expansion"
+        } else {
+            "Expansion failed, showing the last good expansion"
+        };
+
+        let mut text = Text::raw(err.to_string());
+        if let Some(err) = diagnostics::compile_error(err) {
+            self.macro_output(err.macro_output.clone());
+
+            // frames in the proc macro's own crate stand out from the rustc internals
+            if let Some(panic) = &err.panic {
+                text.extend(Text::raw("\n"));
+                text.extend(panic.lines().into_iter().map(|(line, in_macro)| {
+                    if in_macro {
+                        Spans::from(Span::styled(
+                            line,
+                            Style::default()
+                                .fg(Color::Yellow)
+                                .add_modifier(Modifier::BOLD),
+                        ))
+                    } else {
+                        Spans::from(line)
+                    }
+                }));
+            }
+        }
+
+        self.running = None;
+        self.error_scroll = Scroll::new(text.height(), text.width());
+        self.failure = Some((title.to_string(), text));
+    }
+
+    // the macro output pane is only there if the macros printed something
+    pub fn macro_output(&mut self, macro_output: String) {
+        if macro_output != self.macro_output {
+            self.output_scroll = Scroll::for_code(&macro_output);
+            self.macro_output = macro_output;
+        }
+        if self.macro_output.is_empty() && self.focus == Focus::Output {
+            self.focus = Focus::Segment;
+        }
+    }
+
+    // a build went through again
+    pub fn recovered(&mut self) {
+        self.failure = None;
+        if self.focus == Focus::Errors {
+            self.focus = Focus::Segment;
+        }
+    }
+
+    // cycle between no diff, unified and side by side
+    pub fn toggle_diff(&mut self) {
+        self.diff = self.diff.next();
+        self.refresh_scroll();
+    }
+
+    // segment, browser, then the macro output and errors while they are there
+    pub const fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Segment => Focus::Browser,
+            Focus::Browser if !self.macro_output.is_empty() => Focus::Output,
+            Focus::Browser | Focus::Output if self.failure.is_some() => Focus::Errors,
+            Focus::Browser | Focus::Output | Focus::Errors => Focus::Segment,
+        };
+    }
+
+    // scrolling of the macro output and error panes, the segment pane has its own rendering
+    pub const fn pane_scroll(&mut self) -> Option<&mut Scroll> {
+        match self.focus {
+            Focus::Output => Some(&mut self.output_scroll),
+            Focus::Errors => Some(&mut self.error_scroll),
+            Focus::Segment | Focus::Browser => None,
+        }
+    }
+
+    pub fn components(&self) -> Components<'_> {
         let paragraph = Paragraph::new(&*self.expanding)
             .block(Block::default().title("Expanding").borders(Borders::ALL))
             .style(Style::default().fg(Color::White).bg(Color::Black))
             .alignment(Alignment::Left)
             .wrap(Wrap { trim: true });
 
-        let code_block = Paragraph::new(&*self.code_block)
-            .block(Block::default().title("Segment").borders(Borders::ALL))
-            .style(Style::default().fg(Color::White).bg(Color::Black))
-            .scroll(self.scroll.offset());
+        let (code_block, current) = match (self.diff, &self.previous) {
+            (DiffMode::Unified, Some(previous)) => (
+                Paragraph::new(diff::unified(previous, &self.code_block))
+                    .block(self.pane("Segment diff".to_string(), Focus::Segment)),
+                None,
+            ),
+            (DiffMode::SideBySide, Some(previous)) => {
+                let (old, new) = diff::side_by_side(previous, &self.code_block);
+
+                (
+                    Paragraph::new(old).block(self.pane("Previous".to_string(), Focus::Segment)),
+                    Some(
+                        Paragraph::new(new).block(self.pane("Current".to_string(), Focus::Segment)),
+                    ),
+                )
+            }
+            (DiffMode::Off, _) => {
+                let text = self
+                    .highlighted
+                    .clone()
+                    .unwrap_or_else(|| Text::raw(&*self.code_block));
+
+                (
+                    Paragraph::new(text).block(self.pane("Segment".to_string(), Focus::Segment)),
+                    None,
+                )
+            }
+            (_, None) => (
+                Paragraph::new(&*self.code_block).block(self.pane(
+                    "Segment (no previous expansion to diff)".to_string(),
+                    Focus::Segment,
+                )),
+                None,
+            ),
+        };
+        let style = Style::default().fg(Color::White).bg(Color::Black);
+        let code_block = code_block.style(style).scroll(self.scroll.offset());
+        let current = current.map(|e| e.style(style).scroll(self.scroll.offset()));
 
+        let status = match (self.running, self.took) {
+            (Some(since), _) => {
+                let frame = since.elapsed().as_millis() / 100 % SPINNER.len() as u128;
+                format!(
+                    "{} Expanding for {}ms",
+                    SPINNER[frame as usize],
+                    since.elapsed().as_millis()
+                )
+            }
+            (None, _) if self.failure.is_some() => "Build failed".to_string(),
+            (None, Some(took)) if self.skipped > 0 => format!(
+                "Took: {}ms, skipped {} items that do not parse",
+                took.as_millis(),
+                self.skipped
+            ),
+            (None, Some(took)) => format!("Took: {}ms", took.as_millis()),
+            (None, None) => "Not expanded yet".to_string(),
+        };
         let info = Paragraph::new(format!(
-            "Took: {}ms, q for quit, r for reload, arrow keys for scrolling",
-            now.elapsed().as_millis(),
+            "{}, q for quit, r for reload, arrow keys for scrolling, \
+            tab to switch pane, / to filter items, enter to open an item, d for diff",
+            status,
         ))
         .block(Block::default().title("Info").borders(Borders::ALL))
         .style(Style::default().fg(Color::White).bg(Color::Black))
         .wrap(Wrap { trim: true });
 
-        (paragraph, code_block, info)
+        let title = if self.browser.filtering {
+            format!("Items /{}_", self.browser.filter)
+        } else if self.browser.filter.is_empty() {
+            "Items".to_string()
+        } else {
+            format!("Items /{}", self.browser.filter)
+        };
+        let browser = List::new(self.browser.items())
+            .block(self.pane(title, Focus::Browser))
+            .style(Style::default().fg(Color::White).bg(Color::Black))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        let errors = self.failure.as_ref().map(|(title, errors)| {
+            let block = if self.focus == Focus::Errors {
+                self.pane(title.clone(), Focus::Errors)
+            } else {
+                Block::default()
+                    .title(title.as_str())
+                    .borders(Borders::ALL)
+                    .border_style(Style::default().fg(Color::Red))
+            };
+
+            Paragraph::new(errors.clone())
+                .block(block)
+                .style(Style::default().fg(Color::White).bg(Color::Black))
+                .wrap(Wrap { trim: false })
+                .scroll(self.error_scroll.offset())
+        });
+
+        let output = (!self.macro_output.is_empty()).then(|| {
+            Paragraph::new(self.macro_output.as_str())
+                .block(self.pane("Macro output".to_string(), Focus::Output))
+                .style(Style::default().fg(Color::White).bg(Color::Black))
+                .scroll(self.output_scroll.offset())
+        });
+
+        (
+            paragraph, code_block, info, browser, current, errors, output,
+        )
     }
 
-    pub fn scroll_code_block_and_render<B: Backend>(&self, f: &mut Frame<B>, now: Instant) {
-        let components = self.components(now);
+    pub fn scroll_code_block_and_render<B: Backend>(&self, f: &mut Frame<B>) {
+        let components = self.components();
         let scrolled = components.1.clone().scroll(self.scroll.offset());
 
-        self.render(f, (components.0, scrolled, components.2))
+        self.render(
+            f,
+            (
+                components.0,
+                scrolled,
+                components.2,
+                components.3,
+                components.4,
+                components.5,
+                components.6,
+            ),
+        )
     }
 
     pub fn render<B: Backend>(&self, f: &mut Frame<B>, components: Components) {
         let chunks = self.layout.split(f.size());
+        let body = self.body.split(chunks[1]);
+
+        let (paragraph, code_block, info, browser, current, errors, output) = components;
+
+        // the macro output goes under the segment pane
+        let segment = output.map_or(body[1], |output| {
+            let split = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+                .split(body[1]);
 
-        let (paragraph, code_block, info) = components;
+            f.render_widget(output, split[1]);
+            split[0]
+        });
 
         f.render_widget(paragraph, chunks[0]);
-        f.render_widget(code_block, chunks[1]);
+        f.render_stateful_widget(browser, body[0], &mut self.browser.state());
+        if let Some(current) = current {
+            let halves = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(segment);
+
+            f.render_widget(code_block, halves[0]);
+            f.render_widget(current, halves[1]);
+        } else {
+            f.render_widget(code_block, segment);
+        }
         f.render_widget(info, chunks[2]);
+
+        // over the bottom of the segment pane so the top of the code stays visible
+        if let Some(errors) = errors {
+            let height = segment.height / 2;
+            let area = Rect {
+                y: segment.y + segment.height - height,
+                height,
+                ..segment
+            };
+
+            f.render_widget(Clear, area);
+            f.render_widget(errors, area);
+        }
+    }
+
+    // highlighting is slow enough to only do it when the code changes
+    fn highlight(&mut self) {
+        self.highlighted = self
+            .highlighter
+            .as_ref()
+            .and_then(|e| e.highlight(&self.code_block).ok());
+    }
+
+    // scroll bounds of whatever the segment pane shows right now
+    fn refresh_scroll(&mut self) {
+        let (height, width) = match (self.diff, &self.previous) {
+            (DiffMode::Unified, Some(previous)) => {
+                let text = diff::unified(previous, &self.code_block);
+                (text.height(), text.width())
+            }
+            (DiffMode::SideBySide, Some(previous)) => {
+                let (old, new) = diff::side_by_side(previous, &self.code_block);
+                (old.height(), old.width().max(new.width()))
+            }
+            _ => {
+                let text = Text::raw(&*self.code_block);
+                (text.height(), text.width())
+            }
+        };
+
+        self.scroll.resize(height, width.saturating_sub(1));
+    }
+
+    // bordered block, highlighted when it has focus
+    fn pane(&self, title: String, focus: Focus) -> Block<'_> {
+        let block = Block::default().title(title).borders(Borders::ALL);
+
+        if self.focus == focus {
+            block.border_style(Style::default().fg(Color::Yellow))
+        } else {
+            block
+        }
     }
 }
 
@@ -114,6 +447,26 @@ impl Scroll {
         }
     }
 
+    // new bounds, the current offset is kept as far as it fits
+    pub fn resize(&mut self, vs: usize, hs: usize) {
+        self.vs = vs;
+        self.hs = hs;
+        self.vs_state = self.vs_state.min(u16::try_from(vs).unwrap_or(u16::MAX));
+    }
+
+    // scroll bounds from the number of lines and the longest line
+    pub fn for_code(code_block: &str) -> Self {
+        let mut lines = 0;
+        let mut line_array = Vec::new();
+
+        code_block.lines().for_each(|e| {
+            line_array.push(e.len().saturating_sub(1));
+            lines += 1;
+        });
+
+        Self::new(lines, line_array.iter().max().copied().unwrap_or(0))
+    }
+
     pub fn scroll_up(&mut self) {
         if self.vs > self.vs_state.into() {
             self.vs_state += 1
@@ -131,11 +484,11 @@ impl Scroll {
         }
     }
 
-    pub fn scroll_down(&mut self) {
+    pub const fn scroll_down(&mut self) {
         self.vs_state = self.vs_state.saturating_sub(1);
     }
 
-    pub fn scroll_left(&mut self) {
+    pub const fn scroll_left(&mut self) {
         self.hs_state = self.hs_state.saturating_sub(1);
     }
 
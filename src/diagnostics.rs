@@ -0,0 +1,320 @@
+use std::error;
+use std::fmt;
+use std::io::Error;
+
+use serde_json::Value;
+
+// The expansion did not compile, holds the rendered rustc errors
+#[derive(Debug)]
+pub struct CompileError {
+    pub diagnostics: Vec<String>,
+    // what the proc macros printed before it failed
+    pub macro_output: String,
+    // set if a proc macro panicked and --macro-backtrace is on
+    pub panic: Option<MacroPanic>,
+}
+
+// A proc macro panic as printed with -Zproc-macro-backtrace
+#[derive(Debug)]
+pub struct MacroPanic {
+    pub location: String,
+    pub message: String,
+    // crate of the proc macro that panicked, taken from the bridge frames
+    pub krate: Option<String>,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    pub index: String,
+    pub symbol: String,
+    pub location: Option<String>,
+    // the frame is in the crate of the proc macro
+    pub in_macro: bool,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.diagnostics.join("\n").trim_end())
+    }
+}
+
+impl error::Error for CompileError {}
+
+impl MacroPanic {
+    // the panic as lines of text, each saying if it is about the proc macro's own code
+    pub fn lines(&self) -> Vec<(String, bool)> {
+        let mut lines = vec![
+            (format!("proc macro panicked at {}", self.location), true),
+            (self.message.clone(), false),
+        ];
+
+        // everything after the last frame of the macro is proc_macro and rustc internals
+        let last = self.frames.iter().rposition(|frame| frame.in_macro);
+        let shown = last.map_or(self.frames.len(), |last| last + 1);
+
+        let header = self.krate.as_ref().map_or_else(
+            || "stack backtrace:".to_string(),
+            |krate| format!("stack backtrace, frames in `{}` marked with >:", krate),
+        );
+        lines.push((header, false));
+        for frame in &self.frames[..shown] {
+            let marker = if frame.in_macro { '>' } else { ' ' };
+
+            lines.push((
+                format!("{} {:>4}: {}", marker, frame.index, frame.symbol),
+                frame.in_macro,
+            ));
+            if let Some(location) = &frame.location {
+                lines.push((format!("             at {}", location), frame.in_macro));
+            }
+        }
+        if shown < self.frames.len() {
+            lines.push((
+                format!(
+                    "  ({} more frames in proc_macro and rustc)",
+                    self.frames.len() - shown
+                ),
+                false,
+            ));
+        }
+
+        lines
+    }
+}
+
+impl fmt::Display for MacroPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = self.lines();
+        let lines = lines.iter().map(|(line, _)| line.as_str());
+
+        f.write_str(&lines.collect::<Vec<_>>().join("\n"))
+    }
+}
+
+// `--message-format=json` prints cargo's messages on stdout next to the expanded code, split
+// them into the code and the rendered errors
+pub fn split(stdout: &str) -> (String, Vec<String>) {
+    let mut code = String::new();
+    let mut diagnostics = Vec::new();
+
+    for line in stdout.lines() {
+        if !line.starts_with("{\"reason\":") {
+            code.push_str(line);
+            code.push('\n');
+            continue;
+        }
+
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        let message = &message["message"];
+        let is_error = message["level"]
+            .as_str()
+            .is_some_and(|level| level.starts_with("error"));
+
+        if let (true, Some(rendered)) = (is_error, message["rendered"].as_str()) {
+            diagnostics.push(rendered.to_string());
+        }
+    }
+
+    (code, diagnostics)
+}
+
+// cargo's stderr mixes its status lines and warnings with whatever the proc macros printed,
+// keep only the latter (rustc's own messages are on stdout in json)
+pub fn macro_output(stderr: &str) -> String {
+    let mut output = String::new();
+    let mut in_message = false;
+
+    for line in stderr.lines() {
+        if is_status(line) {
+            in_message = false;
+        } else if ["warning:", "error:", "note:", "help:"]
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+        {
+            in_message = true;
+        } else if !(in_message && (line.is_empty() || line.starts_with(' '))) {
+            in_message = false;
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+
+    output
+}
+
+// cargo right aligns the verb of its status lines to 12 columns, `   Compiling foo v0.1.0`
+fn is_status(line: &str) -> bool {
+    line.get(..13).is_some_and(|head| {
+        let (verb, space) = head.split_at(12);
+        let verb = verb.trim_start();
+
+        space == " "
+            && verb.starts_with(char::is_uppercase)
+            && verb.chars().all(char::is_alphabetic)
+    })
+}
+
+// take the first panic out of the macro output, the rest stays output
+pub fn split_panic(output: &str) -> (String, Option<MacroPanic>) {
+    let mut rest = String::new();
+    let mut panic: Option<MacroPanic> = None;
+    let mut lines = output.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let Some(location) = panic_location(line) else {
+            rest.push_str(line);
+            rest.push('\n');
+            continue;
+        };
+
+        let mut message = Vec::new();
+        while let Some(line) = lines.next_if(|line| !is_backtrace_start(line)) {
+            message.push(line);
+        }
+        lines.next_if(|line| *line == "stack backtrace:");
+
+        // `  12: symbol` lines, each optionally followed by `at file:line:col`
+        let mut frames: Vec<Frame> = Vec::new();
+        while let Some(line) = lines.next_if(|line| line.starts_with(' ')) {
+            let line = line.trim();
+
+            if let Some(location) = line.strip_prefix("at ") {
+                if let Some(frame) = frames.last_mut() {
+                    frame.location = Some(location.to_string());
+                }
+            } else if let Some((index, symbol)) = line.split_once(": ") {
+                if index.chars().all(|c| c.is_ascii_digit()) {
+                    frames.push(Frame {
+                        index: index.to_string(),
+                        symbol: symbol.to_string(),
+                        location: None,
+                        in_macro: false,
+                    });
+                }
+            }
+        }
+
+        if panic.is_none() {
+            let krate = macro_crate(&frames);
+            for frame in &mut frames {
+                frame.in_macro = krate.as_ref().is_some_and(|krate| {
+                    frame
+                        .symbol
+                        .trim_start_matches('<')
+                        .starts_with(&format!("{}::", krate))
+                });
+            }
+
+            panic = Some(MacroPanic {
+                location: location.to_string(),
+                message: message.join("\n"),
+                krate,
+                frames,
+            });
+        }
+    }
+
+    (rest, panic)
+}
+
+// `thread '<unnamed>' (20988) panicked at mac/src/lib.rs:7:9:`
+fn panic_location(line: &str) -> Option<&str> {
+    if !line.starts_with("thread '") {
+        return None;
+    }
+
+    line.split_once(" panicked at ")
+        .map(|(_, location)| location.trim_end_matches(':'))
+}
+
+fn is_backtrace_start(line: &str) -> bool {
+    line == "stack backtrace:" || line.starts_with("note: ") || line.starts_with(' ')
+}
+
+// the bridge runs the macro through `expand1::<krate::macro_fn>` (`expand2` for attributes)
+fn macro_crate(frames: &[Frame]) -> Option<String> {
+    frames.iter().find_map(|frame| {
+        let (_, rest) = frame
+            .symbol
+            .split_once("expand1")
+            .or_else(|| frame.symbol.split_once("expand2"))?;
+        let rest = rest.trim_start_matches("::").strip_prefix('<')?;
+
+        rest.split_once("::").map(|(krate, _)| krate.to_string())
+    })
+}
+
+// the rustc errors behind a failed expansion, if that is why it failed
+pub fn compile_error(err: &Error) -> Option<&CompileError> {
+    err.get_ref()?.downcast_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_keeps_errors() {
+        let stdout = r#"pub struct Foo;
+{"reason":"compiler-message","message":{"level":"warning","rendered":"warning: unused"}}
+{"reason":"compiler-message","message":{"level":"error","rendered":"error: cannot find type"}}
+{"reason":"build-finished","success":false}
+fn main() {}
+"#;
+        let (code, diagnostics) = split(stdout);
+
+        assert_eq!(code, "pub struct Foo;\nfn main() {}\n");
+        assert_eq!(diagnostics, ["error: cannot find type"]);
+    }
+
+    #[test]
+    fn macro_output_drops_cargo() {
+        let stderr = "   Compiling sample v0.1.0 (/tmp/sample)
+hello from the macro
+warning: unused variable: `x`
+ --> src/lib.rs:1:1
+  |
+
+printed after
+    Finished `dev` profile [unoptimized + debuginfo] target(s) in 0.10s
+";
+
+        assert_eq!(
+            macro_output(stderr),
+            "hello from the macro\nprinted after\n"
+        );
+    }
+
+    #[test]
+    fn split_panic_frames() {
+        let output = "before
+thread 'rustc' (20988) panicked at mac/src/lib.rs:7:9:
+boom
+stack backtrace:
+   0: std::panicking::begin_panic
+   1: mac::derive_boom
+             at ./mac/src/lib.rs:7:9
+   2: proc_macro::bridge::client::Client<proc_macro::TokenStream,proc_macro::TokenStream>::expand1::<mac::derive_boom>::{{closure}}
+after
+";
+        let (rest, panic) = split_panic(output);
+        let panic = panic.unwrap();
+
+        assert_eq!(rest, "before\nafter\n");
+        assert_eq!(panic.location, "mac/src/lib.rs:7:9");
+        assert_eq!(panic.message, "boom");
+        assert_eq!(panic.krate.as_deref(), Some("mac"));
+        assert_eq!(panic.frames.len(), 3);
+        assert_eq!(
+            panic.frames[1].location.as_deref(),
+            Some("./mac/src/lib.rs:7:9")
+        );
+        let in_macro = panic.frames.iter().map(|f| f.in_macro).collect::<Vec<_>>();
+        assert_eq!(in_macro, [false, true, false]);
+
+        assert!(split_panic("no panic here\n").1.is_none());
+    }
+}
@@ -0,0 +1,117 @@
+use similar::{ChangeTag, DiffOp, TextDiff};
+use tui::{
+    style::{Color, Style},
+    text::{Span, Spans, Text},
+};
+
+// How the segment pane shows the change between two expansions
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DiffMode {
+    Off,
+    Unified,
+    SideBySide,
+}
+
+impl DiffMode {
+    pub const fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Unified,
+            Self::Unified => Self::SideBySide,
+            Self::SideBySide => Self::Off,
+        }
+    }
+}
+
+// one text with `+` and `-` lines
+pub fn unified(old: &str, new: &str) -> Text<'static> {
+    let diff = TextDiff::from_lines(old, new);
+
+    diff.iter_all_changes()
+        .map(|change| {
+            let line = change.value().trim_end_matches('\n');
+            match change.tag() {
+                ChangeTag::Equal => Spans::from(format!("  {}", line)),
+                ChangeTag::Delete => styled(format!("- {}", line), Color::Red),
+                ChangeTag::Insert => styled(format!("+ {}", line), Color::Green),
+            }
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+// old and new next to each other, padded with empty lines so unchanged lines line up
+pub fn side_by_side(old: &str, new: &str) -> (Text<'static>, Text<'static>) {
+    let diff = TextDiff::from_lines(old, new);
+    let old_lines = diff.old_slices();
+    let new_lines = diff.new_slices();
+    let (mut left, mut right) = (Vec::new(), Vec::new());
+
+    let line = |slice: &str| slice.trim_end_matches('\n').to_string();
+
+    for op in diff.ops() {
+        match *op {
+            DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } => {
+                left.extend(
+                    old_lines[old_index..old_index + len]
+                        .iter()
+                        .map(|e| Spans::from(line(e))),
+                );
+                right.extend(
+                    new_lines[new_index..new_index + len]
+                        .iter()
+                        .map(|e| Spans::from(line(e))),
+                );
+            }
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => {
+                left.extend(
+                    old_lines[old_index..old_index + old_len]
+                        .iter()
+                        .map(|e| styled(line(e), Color::Red)),
+                );
+                right.extend((0..old_len).map(|_| Spans::default()));
+            }
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => {
+                left.extend((0..new_len).map(|_| Spans::default()));
+                right.extend(
+                    new_lines[new_index..new_index + new_len]
+                        .iter()
+                        .map(|e| styled(line(e), Color::Green)),
+                );
+            }
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => {
+                let len = old_len.max(new_len);
+                left.extend(
+                    old_lines[old_index..old_index + old_len]
+                        .iter()
+                        .map(|e| styled(line(e), Color::Red))
+                        .chain((old_len..len).map(|_| Spans::default())),
+                );
+                right.extend(
+                    new_lines[new_index..new_index + new_len]
+                        .iter()
+                        .map(|e| styled(line(e), Color::Green))
+                        .chain((new_len..len).map(|_| Spans::default())),
+                );
+            }
+        }
+    }
+
+    (left.into(), right.into())
+}
+
+fn styled(line: String, color: Color) -> Spans<'static> {
+    Spans::from(Span::styled(line, Style::default().fg(color)))
+}
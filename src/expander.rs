@@ -0,0 +1,355 @@
+use crate::cache::Cache;
+use crate::cmd::{Context, Running};
+use crate::diagnostics::{self, CompileError};
+use crate::toolchain::Toolchain;
+use crate::{error_other, Args};
+
+use std::env;
+use std::fs;
+use std::io::{self, Error, ErrorKind, Read, Result};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{MutexGuard, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Produces the expanded source of the crate, the locators and the TUI only ever see its output
+pub trait Expander: Send + Sync {
+    // the expanded code and what the proc macros printed, an expansion cancelled with
+    // `Context::cancel` fails with an `Interrupted` error
+    fn expand(&self, ctx: &Context) -> Result<(String, String)>;
+
+    // the compiler the code is expanded with, for the cache key
+    fn rustc(&self) -> Command {
+        Command::new("rustc")
+    }
+}
+
+// `cargo rustc -- -Zunpretty=expanded` on nightly, the default
+pub struct Rustc {
+    toolchain: Toolchain,
+    // the toolchain was found to take -Zunpretty
+    checked: OnceLock<()>,
+}
+
+// `cargo expand <path>`, for --path
+pub struct CargoExpand {
+    path: String,
+}
+
+// Source that was expanded elsewhere, read from a file or stdin
+pub struct PreExpanded {
+    source: Source,
+}
+
+enum Source {
+    // read again on every expansion, so a watched file picks up changes
+    File(PathBuf),
+    // stdin can only be read once, it is kept
+    Stdin(String),
+}
+
+// the backend the flags ask for
+pub fn from_args(args: &Args) -> Result<Box<dyn Expander>> {
+    if let Some(input) = &args.input {
+        return Ok(Box::new(PreExpanded::new(input)?));
+    }
+
+    Ok(match &args.path {
+        Some(path) => Box::new(CargoExpand { path: path.clone() }),
+        None => Box::new(Rustc::new(args)?),
+    })
+}
+
+impl Expander for Rustc {
+    fn expand(&self, ctx: &Context) -> Result<(String, String)> {
+        // cargo's error for it is buried in the "Cannot expand code" blob
+        if self.checked.get().is_none() {
+            self.toolchain.check()?;
+            let _ = self.checked.set(());
+        }
+
+        run(self.command(ctx), &ctx.running, ctx.cache())
+    }
+
+    fn rustc(&self) -> Command {
+        self.toolchain.command("rustc")
+    }
+}
+
+impl Rustc {
+    // only this backend runs the compiler itself, so only it looks for a toolchain file
+    pub fn new(args: &Args) -> Result<Self> {
+        Ok(Self {
+            toolchain: Toolchain::new(args)?,
+            checked: OnceLock::new(),
+        })
+    }
+
+    // the cargo invocation that prints the expanded crate
+    fn command(&self, ctx: &Context) -> Command {
+        let args = &ctx.args;
+        let mut cmd = self.toolchain.command("cargo");
+
+        cmd.arg("rustc");
+        cmd.args(target_flags(args).unwrap_or_else(|| vec!["--lib".to_string()]));
+        // the test profile makes cargo pass --test to rustc, which turns on cfg(test) and the
+        // test harness
+        let profile = if args.lib_tests { "test" } else { "check" };
+        cmd.arg(format!(
+            "--profile={}",
+            args.profile.as_deref().unwrap_or(profile)
+        ))
+        .args(build_flags(args))
+        .arg("--message-format=json")
+        .arg("--")
+        .arg("-Zunpretty=expanded");
+
+        // a panicking proc macro prints its message and backtrace to stderr
+        if args.macro_backtrace {
+            cmd.arg("-Zproc-macro-backtrace");
+            if env::var_os("RUST_BACKTRACE").is_none() {
+                cmd.env("RUST_BACKTRACE", "1");
+            }
+        }
+
+        cmd
+    }
+}
+
+impl Expander for CargoExpand {
+    fn expand(&self, ctx: &Context) -> Result<(String, String)> {
+        run(self.command(&ctx.args), &ctx.running, ctx.cache())
+    }
+}
+
+impl CargoExpand {
+    fn command(&self, args: &Args) -> Command {
+        let mut cmd = Command::new("cargo");
+
+        cmd.arg("expand")
+            .args(target_flags(args).unwrap_or_default())
+            .args(build_flags(args));
+        if let Some(profile) = &args.profile {
+            cmd.arg("--profile").arg(profile);
+        }
+        cmd.arg(&self.path);
+
+        cmd
+    }
+}
+
+impl Expander for PreExpanded {
+    // there are no proc macros running, so no output from them
+    fn expand(&self, _ctx: &Context) -> Result<(String, String)> {
+        let code = match &self.source {
+            Source::File(path) => fs::read_to_string(path)
+                .map_err(|e| error_other(format!("Cannot read {}: {}", path.display(), e)))?,
+            Source::Stdin(code) => code.clone(),
+        };
+
+        Ok((code, String::new()))
+    }
+}
+
+impl PreExpanded {
+    // `-` reads stdin, right away since it is only there once
+    pub fn new(input: &str) -> Result<Self> {
+        let source = if input == "-" {
+            let mut code = String::new();
+            io::stdin()
+                .read_to_string(&mut code)
+                .map_err(|e| error_other(format!("Cannot read stdin: {}", e)))?;
+            Source::Stdin(code)
+        } else {
+            Source::File(PathBuf::from(input))
+        };
+
+        Ok(Self { source })
+    }
+}
+
+// runs the cargo invocation of a backend, the process is kept in `running` so it can be
+// cancelled. Returns the expanded code and the output of the proc macros, from the cache if
+// nothing changed
+fn run(mut cmd: Command, running: &Running, cache: Option<&Cache>) -> Result<(String, String)> {
+    let key = cache.map(|cache| cache.key(&cmd));
+
+    if let Some(hit) = cache
+        .zip(key.as_ref())
+        .and_then(|(cache, key)| cache.get(key))
+    {
+        return Ok(hit);
+    }
+
+    // in a process group of its own, so cancelling takes rustc and the build scripts with it
+    #[cfg(unix)]
+    cmd.process_group(0);
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // read both pipes while cargo runs so it never blocks on a full one
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    *lock(running)? = Some(child);
+
+    let status = match wait(running)? {
+        Some(status) => status,
+        None => {
+            return Err(Error::new(
+                ErrorKind::Interrupted,
+                "Expansion cancelled".to_string(),
+            ))
+        }
+    };
+    let (stdout, stderr) = (join_pipe(stdout)?, join_pipe(stderr)?);
+
+    let (code, diagnostics) = diagnostics::split(&String::from_utf8_lossy(&stdout));
+    let macro_output = diagnostics::macro_output(&String::from_utf8_lossy(&stderr));
+    let (macro_output, panic) = diagnostics::split_panic(&macro_output);
+
+    if status.success() {
+        // a source that changed while cargo ran may or may not be in the output
+        if let Some((cache, key)) = cache.zip(key) {
+            if cache.key(&cmd) == key {
+                cache.put(&key, &code, &macro_output);
+            }
+        }
+
+        Ok((code, macro_output))
+    } else if !diagnostics.is_empty() {
+        Err(Error::other(CompileError {
+            diagnostics,
+            macro_output,
+            panic,
+        }))
+    } else {
+        // vomit stdout and stderr if it fails for another reason
+        Err(error_other(format!(
+            "Cannot expand code, stdout: {}, stderr: {}",
+            code,
+            String::from_utf8_lossy(&stderr)
+        )))
+    }
+}
+
+// the target to expand, none picks the backend's default
+fn target_flags(args: &Args) -> Option<Vec<String>> {
+    let (flag, name) = if let Some(binary) = &args.binary {
+        ("--bin", binary)
+    } else if let Some(test) = &args.test {
+        ("--test", test)
+    } else if let Some(example) = &args.example {
+        ("--example", example)
+    } else if let Some(bench) = &args.bench {
+        ("--bench", bench)
+    } else if args.lib_tests {
+        // cargo rustc only needs the profile, cargo expand takes --tests for it
+        return Some(if args.path.is_some() {
+            vec!["--lib".to_string(), "--tests".to_string()]
+        } else {
+            vec!["--lib".to_string()]
+        });
+    } else {
+        return None;
+    };
+
+    Some(vec![flag.to_string(), name.clone()])
+}
+
+// flags both backends take as they are, they pick the package and which cfgs are active
+fn build_flags(args: &Args) -> Vec<String> {
+    let mut flags = Vec::new();
+
+    if !args.features.is_empty() {
+        flags.push("--features".to_string());
+        flags.push(args.features.join(","));
+    }
+    if args.all_features {
+        flags.push("--all-features".to_string());
+    }
+    if args.no_default_features {
+        flags.push("--no-default-features".to_string());
+    }
+    if let Some(target) = &args.target {
+        flags.push("--target".to_string());
+        flags.push(target.clone());
+    }
+    if let Some(package) = &args.package {
+        flags.push("--package".to_string());
+        flags.push(package.clone());
+    }
+    if let Some(manifest_path) = &args.manifest_path {
+        flags.push("--manifest-path".to_string());
+        flags.push(manifest_path.clone());
+    }
+
+    flags
+}
+
+// poll instead of blocking on the child so `Context::cancel` can take it, `None` if it did
+fn wait(running: &Running) -> Result<Option<ExitStatus>> {
+    loop {
+        let mut child = lock(running)?;
+
+        match child.as_mut().map(Child::try_wait).transpose()? {
+            Some(Some(status)) => {
+                child.take();
+                return Ok(Some(status));
+            }
+            Some(None) => (),
+            None => return Ok(None),
+        }
+
+        drop(child);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// kill cargo along with everything it started, returning once all of it is gone so the next
+// cargo does not wait on the build directory lock
+#[cfg(unix)]
+pub fn kill(mut child: Child) {
+    let group = -(child.id() as libc::pid_t);
+
+    // SAFETY: signals the group cargo leads, `run` spawned it with `process_group(0)`
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    let _ = child.wait();
+
+    // processes left in the group are reaped by init, give it a moment
+    for _ in 0..25 {
+        // SAFETY: signal 0 only checks whether the group still exists
+        if unsafe { libc::kill(group, 0) } != 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[cfg(not(unix))]
+pub fn kill(mut child: Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn lock(running: &Running) -> Result<MutexGuard<'_, Option<Child>>> {
+    running
+        .lock()
+        .map_err(|e| error_other(format!("Cannot lock expansion: {}", e)))
+}
+
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+fn join_pipe(handle: JoinHandle<Vec<u8>>) -> Result<Vec<u8>> {
+    handle
+        .join()
+        .map_err(|_| error_other("Cannot read cargo output".to_string()))
+}
@@ -0,0 +1,209 @@
+use crate::metadata::Metadata;
+use crate::parse;
+use crate::{error_other, Args};
+
+use std::env;
+use std::io::{Result, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use clap::ValueEnum;
+use syn::visit::{self, Visit};
+use syn::{Expr, File, ForeignItem, ImplItem, Item, Pat, TraitItem, Type};
+
+// What formats the code that is shown
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatterChoice {
+    // prettyplease, in process
+    Builtin,
+    // rustfmt on stdin, with the project's rustfmt.toml
+    Rustfmt,
+}
+
+#[derive(Clone)]
+pub struct Formatter {
+    choice: FormatterChoice,
+    // edition of the package, rustfmt would parse the code as 2015 otherwise
+    edition: String,
+    // rustfmt looks for rustfmt.toml from here up
+    dir: PathBuf,
+}
+
+impl Formatter {
+    // the package is only looked up for rustfmt, expanded input from elsewhere gets 2021
+    pub fn new(args: &Args) -> Self {
+        let package = (args.formatter == FormatterChoice::Rustfmt)
+            .then(|| Metadata::load(args).ok())
+            .flatten()
+            .and_then(|metadata| {
+                metadata
+                    .current()
+                    .first()
+                    .map(|package| (package.edition.clone(), package.dir().to_path_buf()))
+            });
+        let (edition, dir) =
+            package.unwrap_or_else(|| ("2021".to_string(), env::current_dir().unwrap_or_default()));
+
+        Self {
+            choice: args.formatter,
+            edition,
+            dir,
+        }
+    }
+
+    pub fn format(&self, code: &str) -> Result<String> {
+        match self.choice {
+            FormatterChoice::Builtin => unparse(code),
+            FormatterChoice::Rustfmt => self.rustfmt(code),
+        }
+    }
+
+    fn rustfmt(&self, code: &str) -> Result<String> {
+        let mut child = Command::new("rustfmt")
+            .arg("--edition")
+            .arg(&self.edition)
+            .current_dir(&self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                error_other(format!(
+                    "Cannot run rustfmt: {}, `--formatter builtin` formats without it",
+                    e
+                ))
+            })?;
+
+        // written from another thread so rustfmt never blocks on a full stdout meanwhile
+        let stdin = child.stdin.take();
+        let code = code.to_string();
+        let writer = thread::spawn(move || stdin.map(|mut stdin| stdin.write_all(code.as_bytes())));
+        let output = child.wait_with_output()?;
+        let _ = writer.join();
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        } else {
+            Err(error_other(format!(
+                "Cannot format code, stdout: {}, stderr: {}",
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            )))
+        }
+    }
+}
+
+// the code is a token stream printed by croc-look, so it parses on its own unless it is a
+// method, which only parses inside an impl or, without a body, inside a trait. Fails on code
+// syn only keeps as tokens, like let-else, which prettyplease cannot print
+fn unparse(code: &str) -> Result<String> {
+    // the TUI thread formats on every reload and Enter, and parses nothing else that would
+    // forget the spans
+    parse::forget_spans();
+
+    if let Some(file) = parse(code) {
+        return Ok(prettyplease::unparse(&file));
+    }
+
+    let file = ["impl __", "trait __"]
+        .iter()
+        .find_map(|wrapper| parse(&format!("{} {{ {} }}", wrapper, code)))
+        .ok_or_else(|| {
+            error_other(
+                "Cannot format code, prettyplease cannot print the item, `--formatter rustfmt` can"
+                    .to_string(),
+            )
+        })?;
+    let unparsed = prettyplease::unparse(&file);
+    let lines = unparsed.lines().collect::<Vec<_>>();
+    let inner = lines
+        .get(1..lines.len().saturating_sub(1))
+        .unwrap_or_default();
+
+    Ok(inner
+        .iter()
+        .map(|line| line.strip_prefix("    ").unwrap_or(line))
+        .map(|line| format!("{}\n", line))
+        .collect())
+}
+
+// prettyplease panics on whatever syn keeps as verbatim tokens, that does not count as parsed
+fn parse(code: &str) -> Option<File> {
+    let file = syn::parse_file(code).ok()?;
+    let mut verbatim = Verbatim(false);
+    verbatim.visit_file(&file);
+
+    (!verbatim.0).then_some(file)
+}
+
+// Finds the parts of a syntax tree syn left as tokens
+struct Verbatim(bool);
+
+impl<'ast> Visit<'ast> for Verbatim {
+    fn visit_item(&mut self, item: &'ast Item) {
+        self.0 |= matches!(item, Item::Verbatim(_) | Item::Macro2(_));
+        visit::visit_item(self, item);
+    }
+
+    fn visit_impl_item(&mut self, item: &'ast ImplItem) {
+        self.0 |= matches!(item, ImplItem::Verbatim(_));
+        visit::visit_impl_item(self, item);
+    }
+
+    fn visit_trait_item(&mut self, item: &'ast TraitItem) {
+        self.0 |= matches!(item, TraitItem::Verbatim(_));
+        visit::visit_trait_item(self, item);
+    }
+
+    fn visit_foreign_item(&mut self, item: &'ast ForeignItem) {
+        self.0 |= matches!(item, ForeignItem::Verbatim(_));
+        visit::visit_foreign_item(self, item);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        self.0 |= matches!(expr, Expr::Verbatim(_));
+        visit::visit_expr(self, expr);
+    }
+
+    fn visit_type(&mut self, ty: &'ast Type) {
+        self.0 |= matches!(ty, Type::Verbatim(_));
+        visit::visit_type(self, ty);
+    }
+
+    fn visit_pat(&mut self, pat: &'ast Pat) {
+        self.0 |= matches!(pat, Pat::Verbatim(_));
+        visit::visit_pat(self, pat);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unparse_items_and_methods() {
+        assert_eq!(
+            unparse("pub struct Foo { a : u8 , }").unwrap(),
+            "pub struct Foo {\n    a: u8,\n}\n"
+        );
+        assert_eq!(
+            unparse("pub fn build () -> Self { Foo }").unwrap(),
+            "pub fn build() -> Self {\n    Foo\n}\n"
+        );
+        assert_eq!(
+            unparse("fn name (& self) -> String ;").unwrap(),
+            "fn name(&self) -> String;\n"
+        );
+    }
+
+    #[test]
+    fn unparse_let_else() {
+        let code = "fn first (y : Option < u8 >) -> u8 { let Some (x) = y else { return 0 } ; x }";
+        let err = unparse(code).unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("prettyplease cannot print the item"));
+    }
+}
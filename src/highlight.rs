@@ -0,0 +1,128 @@
+use crate::error_other;
+
+use std::io::{self, IsTerminal, Result};
+
+use bat::{assets::HighlightingAssets, PagingMode, PrettyPrinter};
+use clap::ValueEnum;
+use syntect::{easy::HighlightLines, highlighting, util::LinesWithEndings};
+use tui::{
+    style::{Color, Modifier, Style},
+    text::{Span, Spans, Text},
+};
+
+// When to colour the output
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+// Highlights rust code with the syntaxes and themes bundled with bat
+pub struct Highlighter {
+    assets: HighlightingAssets,
+    theme: String,
+}
+
+impl ColorChoice {
+    // auto only colours when printing to a terminal
+    pub fn enabled(self) -> bool {
+        match self {
+            Self::Auto => io::stdout().is_terminal(),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+impl Highlighter {
+    pub fn new(theme: Option<&str>) -> Result<Self> {
+        let assets = HighlightingAssets::from_binary();
+        let theme = theme.unwrap_or_else(|| HighlightingAssets::default_theme());
+
+        if !assets.themes().any(|e| e == theme) {
+            return Err(error_other(format!(
+                "Unknown theme `{}`, available themes: {}",
+                theme,
+                assets.themes().collect::<Vec<_>>().join(", ")
+            )));
+        }
+
+        Ok(Self {
+            assets,
+            theme: theme.to_string(),
+        })
+    }
+
+    // print to stdout with terminal escapes
+    pub fn print(&self, code: &str) -> Result<()> {
+        PrettyPrinter::new()
+            .input_from_bytes(code.as_bytes())
+            .language("rust")
+            .theme(&self.theme)
+            .colored_output(true)
+            .paging_mode(PagingMode::Never)
+            .print()
+            .map(|_| ())
+            .map_err(|e| error_other(format!("Cannot highlight code: {}", e)))
+    }
+
+    // styled lines for the segment pane
+    pub fn highlight(&self, code: &str) -> Result<Text<'static>> {
+        let syntax_set = self
+            .assets
+            .get_syntax_set()
+            .map_err(|e| error_other(format!("Cannot load syntaxes: {}", e)))?;
+        let syntax = syntax_set
+            .find_syntax_by_extension("rs")
+            .ok_or_else(|| error_other("No syntax for rust".to_string()))?;
+        let mut lines = HighlightLines::new(syntax, self.assets.get_theme(&self.theme));
+        let mut text = Vec::new();
+
+        for line in LinesWithEndings::from(code) {
+            let regions = lines
+                .highlight_line(line, syntax_set)
+                .map_err(|e| error_other(format!("Cannot highlight code: {}", e)))?;
+
+            text.push(Spans::from(
+                regions
+                    .into_iter()
+                    .map(|(style, region)| {
+                        Span::styled(region.trim_end_matches('\n').to_string(), tui_style(style))
+                    })
+                    .collect::<Vec<_>>(),
+            ));
+        }
+
+        Ok(text.into())
+    }
+}
+
+fn tui_style(style: highlighting::Style) -> Style {
+    let mut tui_style = Style::default().fg(tui_color(style.foreground));
+
+    if style.font_style.contains(highlighting::FontStyle::BOLD) {
+        tui_style = tui_style.add_modifier(Modifier::BOLD);
+    }
+    if style.font_style.contains(highlighting::FontStyle::ITALIC) {
+        tui_style = tui_style.add_modifier(Modifier::ITALIC);
+    }
+    if style
+        .font_style
+        .contains(highlighting::FontStyle::UNDERLINE)
+    {
+        tui_style = tui_style.add_modifier(Modifier::UNDERLINED);
+    }
+
+    tui_style
+}
+
+// bat's ansi themes keep the palette index in `r` and mark it with an alpha of 0,
+// an alpha of 1 means the terminal's default colour
+const fn tui_color(color: highlighting::Color) -> Color {
+    match color.a {
+        0 => Color::Indexed(color.r),
+        1 => Color::Reset,
+        _ => Color::Rgb(color.r, color.g, color.b),
+    }
+}
@@ -1,197 +1,810 @@
-use std::iter::Peekable;
+use std::fmt;
 
-use proc_macro2::{Delimiter, Ident, Span, TokenStream, TokenTree};
+use proc_macro2::{Ident, Span};
+use quote::ToTokens;
+use syn::parse::{Parse, ParseStream};
+use syn::visit::{self, Visit};
+use syn::{
+    File, GenericArgument, ImplItem, Item, ItemImpl, ItemMod, Path, PathArguments, PathSegment,
+    ReturnType, TraitItem, Type, TypePath,
+};
 
-pub fn get_ident<T: AsRef<str>>(name: T) -> Ident {
-    Ident::new(name.as_ref(), Span::call_site())
+// Kind of an item in the expanded code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ItemKind {
+    Struct,
+    Enum,
+    Union,
+    TypeAlias,
+    Const,
+    Static,
+    Trait,
+    Function,
+    Module,
+    TraitImpl,
+    InherentImpl,
 }
 
-pub fn stream_to_string(vec: Vec<TokenTree>) -> String {
-    let mut stream = TokenStream::new();
-    stream.extend(vec.into_iter());
-    stream.to_string()
+// An item of the expanded code along with the module it lives in
+pub struct IndexedItem {
+    pub kind: ItemKind,
+    pub name: String,
+    pub module_path: Vec<String>,
+    // methods are indexed as functions too, this is the impl or trait they are in, `Foo` or
+    // `<Foo as Clone>`
+    pub owner: Option<String>,
+    pub item: Item,
 }
 
-pub fn get_after_impl(vec: &[TokenTree]) -> Vec<TokenTree> {
-    let mut index = 0;
-    vec.iter().enumerate().for_each(|(inner_index, value)| {
-        if let TokenTree::Ident(ident) = value {
-            if ident == &get_ident("impl") {
-                index = inner_index;
+// Every item of the expanded crate, built once from the parsed file
+pub struct ItemIndex {
+    items: Vec<IndexedItem>,
+}
+
+// An associated item of an impl block, `Foo::build` or `<Foo as Trait>::method`
+pub struct AssocSelector {
+    pub self_ty: Type,
+    pub trait_path: Option<Path>,
+    pub name: Ident,
+}
+
+// Walks the syntax tree and keeps track of the current module
+struct IndexVisitor {
+    module_path: Vec<String>,
+    items: Vec<IndexedItem>,
+}
+
+impl ItemKind {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Struct => "struct",
+            Self::Enum => "enum",
+            Self::Union => "union",
+            Self::TypeAlias => "type",
+            Self::Const => "const",
+            Self::Static => "static",
+            Self::Trait => "trait",
+            Self::Function => "fn",
+            Self::Module => "mod",
+            Self::TraitImpl => "impl trait",
+            Self::InherentImpl => "impl",
+        }
+    }
+
+    // human readable name used in headers, `Expanding type alias: Foo`
+    pub const fn description(self) -> &'static str {
+        match self {
+            Self::Struct => "struct",
+            Self::Enum => "enum",
+            Self::Union => "union",
+            Self::TypeAlias => "type alias",
+            Self::Const => "const",
+            Self::Static => "static",
+            Self::Trait => "trait definition",
+            Self::Function => "function",
+            Self::Module => "module",
+            Self::TraitImpl => "trait impl",
+            Self::InherentImpl => "inherent impl",
+        }
+    }
+}
+
+impl fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl IndexedItem {
+    pub fn code(&self) -> String {
+        self.item.to_token_stream().to_string()
+    }
+
+    // name of the item, `Clone for Foo` for trait impls and `Foo` for inherent impls
+    pub fn title(&self) -> String {
+        match (self.kind, self.self_ty()) {
+            _ if self.owner.is_some() => {
+                format!(
+                    "{}::{}",
+                    self.owner.as_deref().unwrap_or_default(),
+                    self.name
+                )
             }
+            (ItemKind::TraitImpl, Some(ty)) => format!("{} for {}", self.name, type_string(ty)),
+            (ItemKind::InherentImpl, Some(ty)) => type_string(ty),
+            _ => self.name.clone(),
+        }
+    }
+
+    // `crate::a::b` style path of the module containing the item
+    pub fn module(&self) -> String {
+        let mut module = String::from("crate");
+        self.module_path.iter().for_each(|segment| {
+            module.push_str("::");
+            module.push_str(segment);
+        });
+        module
+    }
+
+    // whether the item lives in a module ending in `modules`, or exactly `modules` if anchored
+    pub fn in_module(&self, modules: &[&str], anchored: bool) -> bool {
+        if modules.len() > self.module_path.len()
+            || (anchored && modules.len() != self.module_path.len())
+        {
+            return false;
+        }
+
+        self.module_path[self.module_path.len() - modules.len()..]
+            .iter()
+            .zip(modules)
+            .all(|(module, expected)| module == expected)
+    }
+
+    // the trait a trait impl is for
+    pub const fn trait_path(&self) -> Option<&Path> {
+        match &self.item {
+            Item::Impl(ItemImpl {
+                trait_: Some((_, path, _)),
+                ..
+            }) => Some(path),
+            _ => None,
+        }
+    }
+
+    // see `--impl-for`, the self type is resolved against the module of the impl
+    pub fn self_ty_matches(&self, pattern: &Type) -> bool {
+        self_ty_matches(pattern, self)
+    }
+
+    // the type an impl block is for, `None` for anything that is not an impl
+    pub const fn self_ty(&self) -> Option<&Type> {
+        match &self.item {
+            Item::Impl(item) => Some(&item.self_ty),
+            _ => None,
+        }
+    }
+}
+
+// `struct Foo in crate::a::b` or `impl Debug for Foo in crate::a::b`
+impl fmt::Display for IndexedItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ItemKind::TraitImpl | ItemKind::InherentImpl => write!(f, "impl ")?,
+            kind => write!(f, "{} ", kind)?,
+        }
+        write!(f, "{} in {}", self.title(), self.module())
+    }
+}
+
+impl ItemIndex {
+    pub fn new(file: &File) -> Self {
+        let mut visitor = IndexVisitor {
+            module_path: Vec::new(),
+            items: Vec::new(),
+        };
+        visitor.visit_file(file);
+
+        Self {
+            items: visitor.items,
+        }
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = &IndexedItem> {
+        self.items.iter()
+    }
+
+    // `name` can be qualified with the modules the item is in, `codec::Frame` matches
+    // `Frame` in any module ending in `codec`, `crate::net::codec::Frame` only the one
+    pub fn find<'a>(
+        &'a self,
+        kind: ItemKind,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a IndexedItem> {
+        let (anchored, mut modules) = split_qualified(name);
+        let name = modules.pop().unwrap_or_default();
+
+        self.items.iter().filter(move |item| {
+            item.kind == kind && item.name == name && item.in_module(&modules, anchored)
+        })
+    }
+
+    pub fn trait_impls<'a>(
+        &'a self,
+        name: &'a str,
+        impl_for: Option<&'a Type>,
+    ) -> impl Iterator<Item = &'a IndexedItem> {
+        // a qualified trait like `fmt::Debug` is matched against the path of the trait
+        let trait_path = syn::parse_str::<Path>(name).ok();
+
+        self.items.iter().filter(move |item| {
+            item.kind == ItemKind::TraitImpl
+                && trait_path
+                    .as_ref()
+                    .is_some_and(|pattern| trait_matches(pattern, item))
+                && impl_for.is_none_or(|pattern| self_ty_matches(pattern, item))
+        })
+    }
+
+    // every `impl Foo { ... }` block for the type
+    pub fn inherent_impls<'a>(&'a self, ty: &'a Type) -> impl Iterator<Item = &'a IndexedItem> {
+        self.items
+            .iter()
+            .filter(move |item| item.kind == ItemKind::InherentImpl && self_ty_matches(ty, item))
+    }
+
+    // the associated item and the impl block it is in, inherent impls are searched
+    // before trait impls unless the selector names a trait
+    pub fn associated_item<'a>(
+        &'a self,
+        selector: &'a AssocSelector,
+    ) -> Option<(&'a IndexedItem, &'a ImplItem)> {
+        let inherent = self.inherent_impls(&selector.self_ty);
+        let traits = self.items.iter().filter(|item| {
+            item.kind == ItemKind::TraitImpl
+                && self_ty_matches(&selector.self_ty, item)
+                && selector
+                    .trait_path
+                    .as_ref()
+                    .is_none_or(|pattern| trait_matches(pattern, item))
+        });
+
+        let impls: Box<dyn Iterator<Item = &IndexedItem> + 'a> = match selector.trait_path {
+            Some(_) => Box::new(traits),
+            None => Box::new(inherent.chain(traits)),
+        };
+
+        impls
+            .filter_map(|item| match &item.item {
+                Item::Impl(block) => Some((item, block)),
+                _ => None,
+            })
+            .find_map(|(item, block)| {
+                block
+                    .items
+                    .iter()
+                    .find(|impl_item| impl_item_ident(impl_item) == Some(&selector.name))
+                    .map(|impl_item| (item, impl_item))
+            })
+    }
+}
+
+impl<'ast> Visit<'ast> for IndexVisitor {
+    fn visit_item(&mut self, item: &'ast Item) {
+        if let Some((kind, name)) = describe(item) {
+            self.items.push(IndexedItem {
+                kind,
+                name,
+                module_path: self.module_path.clone(),
+                owner: None,
+                item: item.clone(),
+            });
         }
-    });
-
-    let slice = &vec[index..];
-    slice.to_vec()
-}
-
-pub fn loc_trait_impl(name: &str, code: TokenStream, impl_for: Option<&str>) -> Option<String> {
-    fn find_after_for(
-        iter: &mut Peekable<impl Iterator<Item = TokenTree>>,
-        collection: &mut Vec<TokenTree>,
-        impl_for: Option<&str>,
-    ) -> Option<String> {
-        while let Some(token) = iter.peek() {
-            match token {
-                TokenTree::Ident(ident) if ident == &get_ident("for") => {
-                    if let Some(struct_ident) = impl_for {
-                        collection.push(iter.next().unwrap());
-                        if let Some(next_ident) = iter.peek() {
-                            match next_ident {
-                                TokenTree::Ident(next_ident) => {
-                                    if next_ident != struct_ident {
-                                        return None;
-                                    }
-                                }
-                                _ => return None,
-                            }
+        self.push_methods(item);
+
+        visit::visit_item(self, item);
+    }
+
+    fn visit_item_mod(&mut self, item: &'ast ItemMod) {
+        self.module_path.push(item.ident.to_string());
+        visit::visit_item_mod(self, item);
+        self.module_path.pop();
+    }
+}
+
+impl IndexVisitor {
+    // the methods of an impl or trait, so `--function` finds them like the free functions
+    fn push_methods(&mut self, item: &Item) {
+        let (owner, methods) = match item {
+            Item::Impl(block) => {
+                let ty = type_string(&block.self_ty);
+                let owner = match describe_impl(block) {
+                    Some((ItemKind::TraitImpl, name)) => format!("<{} as {}>", ty, name),
+                    _ => ty,
+                };
+                let methods = block
+                    .items
+                    .iter()
+                    .filter_map(|item| match item {
+                        ImplItem::Method(method) => {
+                            Some((&method.sig.ident, method.to_token_stream()))
                         }
-                    }
-                    while let Some(token) = iter.peek() {
-                        match token {
-                            TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
-                                let mut stream = TokenStream::new();
-                                // UNWRAP: iter.peek() is some
-                                collection.push(iter.next().unwrap());
-                                stream.extend(get_after_impl(collection).into_iter());
-
-                                return Some(stream.to_string());
-                            }
-                            // UNWRAP: iter.peek() is some
-                            _ => collection.push(iter.next().unwrap()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                (owner, methods)
+            }
+            Item::Trait(block) => {
+                let methods = block
+                    .items
+                    .iter()
+                    .filter_map(|item| match item {
+                        TraitItem::Method(method) => {
+                            Some((&method.sig.ident, method.to_token_stream()))
                         }
-                    }
-                }
-                // UNWRAP: iter.peek() is some
-                _ => collection.push(iter.next().unwrap()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                (block.ident.to_string(), methods)
             }
+            _ => return,
+        };
+
+        for (ident, tokens) in methods {
+            self.items.push(IndexedItem {
+                kind: ItemKind::Function,
+                name: ident.to_string(),
+                module_path: self.module_path.clone(),
+                owner: Some(owner.clone()),
+                item: Item::Verbatim(tokens),
+            });
         }
-        None
     }
+}
 
-    let mut iter = code.into_iter().peekable();
+impl Parse for AssocSelector {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let TypePath { qself, path } = input.parse()?;
+        let mut segments = path.segments.into_iter().collect::<Vec<_>>();
 
-    while let Some(tree) = iter.next() {
-        match tree {
-            TokenTree::Group(group) => {
-                if let Some(string) = loc_trait_impl(name, group.stream(), impl_for) {
-                    return Some(string);
+        let last = segments
+            .pop()
+            .ok_or_else(|| input.error("expected an associated item"))?;
+        if !last.arguments.is_empty() {
+            return Err(input.error("associated item cannot have generic arguments"));
+        }
+
+        let (self_ty, trait_path) = match qself {
+            // `<Foo as Trait>::method`
+            Some(qself) if qself.position > 0 => {
+                if qself.position != segments.len() {
+                    return Err(input.error("expected `<Type as Trait>::item`"));
                 }
+                let trait_path = Path {
+                    leading_colon: path.leading_colon,
+                    segments: segments.into_iter().collect(),
+                };
+
+                (*qself.ty, Some(trait_path))
             }
-            TokenTree::Ident(ref ident) => {
-                if ident == &get_ident("impl") {
-                    let mut collection = vec![tree];
-                    while let Some(x) = iter.next() {
-                        match x {
-                            TokenTree::Ident(ref ident) if ident == &get_ident(name) => {
-                                collection.push(x);
-                                if let Some(x) =
-                                    find_after_for(&mut iter, &mut collection, impl_for)
-                                {
-                                    return Some(x);
-                                }
-                            }
-                            TokenTree::Group(group) => {
-                                if let Some(string) = loc_trait_impl(name, group.stream(), impl_for)
-                                {
-                                    return Some(string);
-                                }
-                            }
-                            // UNWRAP: iter.peek() is some
-                            _ => collection.push(x),
-                        }
-                    }
-                }
+            // `<Foo>::method`
+            Some(qself) if segments.is_empty() => (*qself.ty, None),
+            Some(_) => return Err(input.error("expected `<Type>::item`")),
+            // `Foo::method`
+            None if segments.is_empty() => {
+                return Err(input.error("expected `Type::item` or `<Type as Trait>::item`"))
             }
-            _ => (),
+            None => {
+                let ty = Type::Path(TypePath {
+                    qself: None,
+                    path: Path {
+                        leading_colon: path.leading_colon,
+                        segments: segments.into_iter().collect(),
+                    },
+                });
+
+                (ty, None)
+            }
+        };
+
+        Ok(Self {
+            self_ty,
+            trait_path,
+            name: last.ident,
+        })
+    }
+}
+
+fn describe(item: &Item) -> Option<(ItemKind, String)> {
+    let described = match item {
+        Item::Struct(item) => (ItemKind::Struct, item.ident.to_string()),
+        Item::Enum(item) => (ItemKind::Enum, item.ident.to_string()),
+        Item::Union(item) => (ItemKind::Union, item.ident.to_string()),
+        Item::Type(item) => (ItemKind::TypeAlias, item.ident.to_string()),
+        Item::Const(item) => (ItemKind::Const, item.ident.to_string()),
+        Item::Static(item) => (ItemKind::Static, item.ident.to_string()),
+        Item::Trait(item) => (ItemKind::Trait, item.ident.to_string()),
+        Item::Fn(item) => (ItemKind::Function, item.sig.ident.to_string()),
+        Item::Mod(item) => (ItemKind::Module, item.ident.to_string()),
+        Item::Impl(item) => describe_impl(item)?,
+        _ => return None,
+    };
+
+    Some(described)
+}
+
+fn describe_impl(item: &ItemImpl) -> Option<(ItemKind, String)> {
+    match &item.trait_ {
+        Some((_, path, _)) => path
+            .segments
+            .last()
+            .map(|segment| (ItemKind::TraitImpl, segment.ident.to_string())),
+        None => type_ident(&item.self_ty).map(|ident| (ItemKind::InherentImpl, ident)),
+    }
+}
+
+const fn impl_item_ident(item: &ImplItem) -> Option<&Ident> {
+    match item {
+        ImplItem::Method(item) => Some(&item.sig.ident),
+        ImplItem::Const(item) => Some(&item.ident),
+        ImplItem::Type(item) => Some(&item.ident),
+        _ => None,
+    }
+}
+
+// last ident of a type path, `Foo` for `a::Foo<T>`
+fn type_ident(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(ty) => ty
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+// type as it would be written in source, `Wrapper<T>` instead of `Wrapper < T >`
+pub fn type_string(ty: &Type) -> String {
+    ty.to_token_stream()
+        .to_string()
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace(" < ", "<")
+        .replace(" >", ">")
+        .replace(" ,", ",")
+        .replace("& ", "&")
+}
+
+// Compare the self type of an impl against a type pattern like `Wrapper<_>`, `a::Foo` or `&Foo`.
+// The self type is resolved against the module the impl is declared in, so `a::Foo` matches
+// `impl Clone for Foo` inside `mod a`
+fn self_ty_matches(pattern: &Type, item: &IndexedItem) -> bool {
+    match (unwrap_type(pattern), item.self_ty().map(unwrap_type)) {
+        (Type::Path(pattern), Some(Type::Path(ty)))
+            if pattern.qself.is_none() && ty.qself.is_none() =>
+        {
+            let (anchored, pattern) = strip_crate(&pattern.path);
+            let resolved = resolve_path(&item.module_path, &ty.path);
+
+            segments_match(&pattern, &resolved, anchored)
         }
+        (pattern, Some(ty)) => type_matches(pattern, ty),
+        _ => false,
+    }
+}
+
+// segments of `crate::net::Frame` or `net::Frame`, and whether it started at the crate root
+fn split_qualified(name: &str) -> (bool, Vec<&str>) {
+    let mut segments = name.split("::").map(str::trim).collect::<Vec<_>>();
+    let anchored = segments.first() == Some(&"crate");
+    if anchored {
+        segments.remove(0);
     }
 
-    None
+    (anchored, segments)
 }
 
-pub fn loc_struct(name: &str, code: TokenStream) -> Option<String> {
-    let mut iter = code.into_iter().peekable();
+// `Clone` matches the trait of `impl ::core::clone::Clone for Foo`
+fn trait_matches(pattern: &Path, item: &IndexedItem) -> bool {
+    item.trait_path().is_some_and(|path| {
+        let (anchored, pattern) = strip_crate(pattern);
+        let (_, path) = strip_crate(path);
 
-    while let Some(tree) = iter.next() {
-        match tree {
-            TokenTree::Group(group) => {
-                if let Some(string) = loc_struct(name, group.stream()) {
-                    return Some(string);
-                }
-            }
-            TokenTree::Ident(ref ident) => {
-                if ident == &get_ident("struct") {
-                    if let Some(TokenTree::Ident(ident)) = iter.peek() {
-                        if ident == &get_ident(name) {
-                            let clone = ident.clone();
-                            iter.next();
-                            let mut collection = vec![tree, TokenTree::Ident(clone)];
-
-                            for x in iter.by_ref() {
-                                if let TokenTree::Group(_) = x {
-                                    collection.push(x);
-                                    if let Some(TokenTree::Punct(punct)) = iter.peek() {
-                                        if ';' == punct.as_char() {
-                                            collection.push(iter.next().unwrap());
-                                        }
-                                    }
-
-                                    return Some(stream_to_string(collection));
-                                }
-                                collection.push(x);
-                            }
-                        }
-                    }
+        segments_match(&pattern, &path, anchored)
+    })
+}
+
+// structural comparison where `_` matches any type and missing generics match any generics
+fn type_matches(pattern: &Type, ty: &Type) -> bool {
+    match (unwrap_type(pattern), unwrap_type(ty)) {
+        (Type::Infer(_), _) => true,
+        (Type::Path(pattern), Type::Path(ty)) if pattern.qself.is_none() && ty.qself.is_none() => {
+            let (anchored, pattern) = strip_crate(&pattern.path);
+            let (_, ty) = strip_crate(&ty.path);
+
+            segments_match(&pattern, &ty, anchored)
+        }
+        (Type::Reference(pattern), Type::Reference(ty)) => {
+            let lifetime_matches = match (&pattern.lifetime, &ty.lifetime) {
+                (None, _) => true,
+                (Some(pattern), _) if pattern.ident == "_" => true,
+                (Some(pattern), Some(ty)) => pattern.ident == ty.ident,
+                (Some(_), None) => false,
+            };
+
+            lifetime_matches
+                && pattern.mutability.is_some() == ty.mutability.is_some()
+                && type_matches(&pattern.elem, &ty.elem)
+        }
+        (Type::Ptr(pattern), Type::Ptr(ty)) => {
+            pattern.mutability.is_some() == ty.mutability.is_some()
+                && type_matches(&pattern.elem, &ty.elem)
+        }
+        (Type::Slice(pattern), Type::Slice(ty)) => type_matches(&pattern.elem, &ty.elem),
+        (Type::Array(pattern), Type::Array(ty)) => {
+            type_matches(&pattern.elem, &ty.elem) && tokens_eq(&pattern.len, &ty.len)
+        }
+        (Type::Tuple(pattern), Type::Tuple(ty)) => {
+            pattern.elems.len() == ty.elems.len()
+                && pattern
+                    .elems
+                    .iter()
+                    .zip(ty.elems.iter())
+                    .all(|(pattern, ty)| type_matches(pattern, ty))
+        }
+        (pattern, ty) => tokens_eq(pattern, ty),
+    }
+}
+
+// the pattern segments have to be a suffix of the target segments, or all of them if anchored
+fn segments_match(pattern: &[PathSegment], target: &[PathSegment], anchored: bool) -> bool {
+    if pattern.len() > target.len() || (anchored && pattern.len() != target.len()) {
+        return false;
+    }
+
+    pattern
+        .iter()
+        .zip(&target[target.len() - pattern.len()..])
+        .all(|(pattern, target)| {
+            pattern.ident == target.ident && arguments_match(&pattern.arguments, &target.arguments)
+        })
+}
+
+fn arguments_match(pattern: &PathArguments, target: &PathArguments) -> bool {
+    match (pattern, target) {
+        (PathArguments::None, _) => true,
+        (PathArguments::AngleBracketed(pattern), PathArguments::AngleBracketed(target)) => {
+            pattern.args.len() == target.args.len()
+                && pattern
+                    .args
+                    .iter()
+                    .zip(target.args.iter())
+                    .all(|(pattern, target)| generic_argument_matches(pattern, target))
+        }
+        (PathArguments::Parenthesized(pattern), PathArguments::Parenthesized(target)) => {
+            let output_matches = match (&pattern.output, &target.output) {
+                (ReturnType::Default, ReturnType::Default) => true,
+                (ReturnType::Type(_, pattern), ReturnType::Type(_, target)) => {
+                    type_matches(pattern, target)
                 }
-            }
-            _ => (),
+                _ => false,
+            };
+
+            output_matches
+                && pattern.inputs.len() == target.inputs.len()
+                && pattern
+                    .inputs
+                    .iter()
+                    .zip(target.inputs.iter())
+                    .all(|(pattern, target)| type_matches(pattern, target))
         }
+        _ => false,
     }
+}
 
-    None
+fn generic_argument_matches(pattern: &GenericArgument, target: &GenericArgument) -> bool {
+    match (pattern, target) {
+        (GenericArgument::Type(pattern), GenericArgument::Type(target)) => {
+            type_matches(pattern, target)
+        }
+        (GenericArgument::Lifetime(pattern), GenericArgument::Lifetime(target)) => {
+            pattern.ident == "_" || pattern.ident == target.ident
+        }
+        (pattern, target) => tokens_eq(pattern, target),
+    }
 }
 
-pub fn loc_function(name: &str, code: TokenStream) -> Option<String> {
-    let mut iter = code.into_iter().peekable();
+// absolute segments of a path written inside the module at `module_path`
+fn resolve_path(module_path: &[String], path: &Path) -> Vec<PathSegment> {
+    let mut segments = path.segments.iter().peekable();
+    let mut resolved: Vec<PathSegment> = Vec::new();
 
-    while let Some(tree) = iter.next() {
-        match tree {
-            TokenTree::Group(group) => {
-                if let Some(string) = loc_function(name, group.stream()) {
-                    return Some(string);
-                }
-            }
-            TokenTree::Ident(ref ident) => {
-                if ident == &get_ident("fn") {
-                    if let Some(TokenTree::Ident(ident)) = iter.peek() {
-                        if ident == &get_ident(name) {
-                            let clone = ident.clone();
-                            iter.next();
-                            let mut collection = vec![tree, TokenTree::Ident(clone)];
-
-                            for x in iter.by_ref() {
-                                if let TokenTree::Group(ref grp) = x {
-                                    let delimiter = grp.delimiter();
-
-                                    collection.push(x);
-
-                                    // check if we arrived at the function body
-                                    if delimiter == Delimiter::Brace {
-                                        return Some(stream_to_string(collection));
-                                    }
-                                } else {
-                                    collection.push(x);
-                                }
-                            }
-                        }
-                    }
+    if path.leading_colon.is_some() {
+        return segments.cloned().collect();
+    }
+
+    match segments.peek() {
+        Some(segment) if segment.ident == "crate" => {
+            segments.next();
+        }
+        Some(segment) if segment.ident == "self" || segment.ident == "super" => {
+            let mut depth = module_path.len();
+            while let Some(segment) = segments.peek() {
+                if segment.ident == "super" {
+                    depth = depth.saturating_sub(1);
+                } else if segment.ident != "self" {
+                    break;
                 }
+                segments.next();
             }
-            _ => (),
+            resolved.extend(module_path[..depth].iter().map(|name| module_segment(name)));
         }
+        _ => resolved.extend(module_path.iter().map(|name| module_segment(name))),
     }
 
-    None
+    resolved.extend(segments.cloned());
+    resolved
+}
+
+fn module_segment(name: &str) -> PathSegment {
+    PathSegment::from(Ident::new(name, Span::call_site()))
+}
+
+// segments without a leading `crate`, and whether it was there
+fn strip_crate(path: &Path) -> (bool, Vec<PathSegment>) {
+    let mut segments = path.segments.iter().cloned().collect::<Vec<_>>();
+    let anchored = segments.first().is_some_and(|first| first.ident == "crate");
+    if anchored {
+        segments.remove(0);
+    }
+
+    (anchored, segments)
+}
+
+fn unwrap_type(ty: &Type) -> &Type {
+    match ty {
+        Type::Paren(ty) => unwrap_type(&ty.elem),
+        Type::Group(ty) => unwrap_type(&ty.elem),
+        _ => ty,
+    }
+}
+
+fn tokens_eq<T: ToTokens>(pattern: &T, target: &T) -> bool {
+    pattern.to_token_stream().to_string() == target.to_token_stream().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+pub struct Foo;
+impl Foo {
+    pub fn build() -> Self {
+        Foo
+    }
+}
+impl ::core::clone::Clone for Foo {
+    fn clone(&self) -> Foo {
+        Foo
+    }
+}
+pub trait Named {
+    fn name(&self) -> String;
+}
+mod a {
+    pub struct Foo;
+    impl Clone for Foo {
+        fn clone(&self) -> Self {
+            Foo
+        }
+    }
+    mod b {
+        fn build() {}
+    }
+}
+"#;
+
+    fn index() -> ItemIndex {
+        ItemIndex::new(&syn::parse_file(SOURCE).unwrap())
+    }
+
+    fn ty(ty: &str) -> Type {
+        syn::parse_str(ty).unwrap()
+    }
+
+    fn resolve(module_path: &[&str], path: &str) -> String {
+        let module_path = module_path
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<_>>();
+        let segments = resolve_path(&module_path, &syn::parse_str(path).unwrap());
+
+        segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect::<Vec<_>>()
+            .join("::")
+    }
+
+    #[test]
+    fn type_matches_patterns() {
+        let matches = |pattern: &str, target: &str| type_matches(&ty(pattern), &ty(target));
+
+        assert!(matches("_", "Vec<u8>"));
+        assert!(matches("Vec<_>", "Vec<u8>"));
+        assert!(matches("Vec", "Vec<u8>"));
+        assert!(!matches("Vec<u16>", "Vec<u8>"));
+        assert!(matches("Foo", "a::Foo"));
+        assert!(!matches("b::Foo", "a::Foo"));
+        assert!(matches("crate::a::Foo", "a::Foo"));
+        assert!(!matches("crate::Foo", "a::Foo"));
+        assert!(matches("&'_ str", "&'a str"));
+        assert!(!matches("&Foo", "&mut Foo"));
+        assert!(matches("(u8, _)", "(u8, u16)"));
+        assert!(!matches("[u8; 4]", "[u8; 5]"));
+        assert!(matches("Fn(_) -> u8", "Fn(u16) -> u8"));
+    }
+
+    #[test]
+    fn resolve_path_against_module() {
+        assert_eq!(resolve(&["a", "b"], "Foo"), "a::b::Foo");
+        assert_eq!(resolve(&["a", "b"], "self::Foo"), "a::b::Foo");
+        assert_eq!(resolve(&["a", "b"], "super::Foo"), "a::Foo");
+        assert_eq!(resolve(&["a", "b"], "super::super::super::Foo"), "Foo");
+        assert_eq!(resolve(&["a", "b"], "crate::c::Foo"), "c::Foo");
+        assert_eq!(resolve(&["a", "b"], "::std::Foo"), "std::Foo");
+    }
+
+    #[test]
+    fn find_qualified_items() {
+        let index = index();
+
+        assert_eq!(index.find(ItemKind::Struct, "Foo").count(), 2);
+        assert_eq!(index.find(ItemKind::Struct, "a::Foo").count(), 1);
+        assert_eq!(index.find(ItemKind::Struct, "crate::Foo").count(), 1);
+        assert_eq!(index.find(ItemKind::Struct, "crate::b::Foo").count(), 0);
+    }
+
+    #[test]
+    fn methods_are_functions() {
+        let index = index();
+        let titles = |name| {
+            index
+                .find(ItemKind::Function, name)
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            titles("build"),
+            ["fn Foo::build in crate", "fn build in crate::a::b"]
+        );
+        assert_eq!(titles("name"), ["fn Named::name in crate"]);
+        assert_eq!(
+            titles("clone"),
+            [
+                "fn <Foo as Clone>::clone in crate",
+                "fn <Foo as Clone>::clone in crate::a"
+            ]
+        );
+    }
+
+    #[test]
+    fn trait_impls_by_self_type() {
+        let index = index();
+        let modules = |name, impl_for: Option<&Type>| {
+            index
+                .trait_impls(name, impl_for)
+                .map(IndexedItem::module)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(modules("Clone", None), ["crate", "crate::a"]);
+        // the impl in `a` names the trait without a path
+        assert_eq!(modules("clone::Clone", None), ["crate"]);
+        assert_eq!(modules("Clone", Some(&ty("a::Foo"))), ["crate::a"]);
+        assert_eq!(modules("Clone", Some(&ty("crate::Foo"))), ["crate"]);
+        assert!(modules("Debug", None).is_empty());
+    }
+
+    #[test]
+    fn associated_item_prefers_inherent_impls() {
+        let index = index();
+        let find = |selector: &str| {
+            let selector: AssocSelector = syn::parse_str(selector).unwrap();
+            index
+                .associated_item(&selector)
+                .map(|(item, _)| item.to_string())
+        };
+
+        assert_eq!(find("Foo::build").as_deref(), Some("impl Foo in crate"));
+        assert_eq!(
+            find("<a::Foo as Clone>::clone").as_deref(),
+            Some("impl Clone for Foo in crate::a")
+        );
+        assert_eq!(find("Foo::missing"), None);
+    }
 }
@@ -1,10 +1,18 @@
 #![warn(clippy::all, clippy::nursery, clippy::cargo)]
+#![allow(clippy::multiple_crate_versions)]
 
 use crate::cmd::{Context, Event};
-use crate::croc_tui::CrocTui;
-use crate::watch::{watch, watch_events};
+use crate::croc_tui::{CrocTui, Focus};
+use crate::format::FormatterChoice;
+use crate::highlight::{ColorChoice, Highlighter};
+use crate::locate::{ItemIndex, ItemKind};
+use crate::metadata::Metadata;
+use crate::query::Query;
+use crate::watch::{roots, watch, watch_events};
+use crate::worker::{Expansion, Worker};
 
 use std::io::{self, Error, ErrorKind, Result, Stdout};
+use std::process;
 use std::time::Instant;
 
 use clap::Parser;
@@ -16,10 +24,22 @@ use hotwatch::Hotwatch;
 use loading::Loading;
 use tui::{backend::CrosstermBackend, Terminal};
 
+mod browser;
+mod cache;
 mod cmd;
 mod croc_tui;
+mod diagnostics;
+mod diff;
+mod expander;
+mod format;
+mod highlight;
 mod locate;
+mod metadata;
+mod parse;
+mod query;
+mod toolchain;
 mod watch;
+mod worker;
 
 type CrocTerminal = Terminal<CrosstermBackend<Stdout>>;
 
@@ -32,38 +52,211 @@ pub struct Args {
     /// Find the struct for which the impl is for (won't work if trait-impl (-t) is not set)
     #[clap(short, long, value_parser)]
     impl_for: Option<String>,
-    /// Pass the --binary BINARY flag to cargo rustc to expand lib, if not specified, --lib is used
+    /// List every impl of the trait (-t) instead of only the first one
+    #[clap(short, long, action)]
+    all: bool,
+    /// Show the inherent impl blocks (`impl Foo { ... }`) of a type
+    #[clap(long, value_parser)]
+    inherent: Option<String>,
+    /// Show a single method or associated item, `Foo::build` or `<Foo as Trait>::method`
+    #[clap(short, long, value_parser)]
+    method: Option<String>,
+    /// Selector like `impl Serialize for Config in config`, `fn main` or `struct *Request`,
+    /// can be given multiple times and combined with the other flags
     #[clap(short, long, value_parser)]
+    query: Vec<String>,
+    /// Pass the --binary BINARY flag to cargo rustc to expand lib, if not specified, --lib is used
+    #[clap(short, long, value_parser, group = "cargo_target")]
     binary: Option<String>,
+    /// Expand the integration test in tests/NAME.rs, with its #[test] functions
+    #[clap(long, value_parser, group = "cargo_target")]
+    test: Option<String>,
+    /// Expand the example in examples/NAME.rs
+    #[clap(long, value_parser, group = "cargo_target")]
+    example: Option<String>,
+    /// Expand the benchmark in benches/NAME.rs
+    #[clap(long, value_parser, group = "cargo_target")]
+    bench: Option<String>,
+    /// Expand the lib as it is built for `cargo test`, with its #[cfg(test)] modules
+    #[clap(long, action, group = "cargo_target", conflicts_with = "profile")]
+    lib_tests: bool,
+    /// Read already expanded source from the file, or stdin with `-`, instead of expanding
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &[
+            "binary", "test", "example", "bench", "lib-tests", "path", "package", "manifest-path",
+            "features", "all-features", "no-default-features", "target", "profile",
+            "macro-backtrace", "toolchain", "rustc-bootstrap",
+        ]
+    )]
+    input: Option<String>,
     /// Use cargo expand <path>
     #[clap(short, long, value_parser)]
     path: Option<String>,
+    /// Workspace member to expand (cargo's -p, which is --path here)
+    #[clap(short = 'P', long, value_parser)]
+    package: Option<String>,
+    /// Path to the Cargo.toml of the package or workspace
+    #[clap(long, value_parser)]
+    manifest_path: Option<String>,
+    /// Features to activate, space or comma separated, can be given multiple times
+    #[clap(short = 'F', long, value_parser)]
+    features: Vec<String>,
+    /// Activate all the features of the package
+    #[clap(long, action)]
+    all_features: bool,
+    /// Do not activate the default feature
+    #[clap(long, action)]
+    no_default_features: bool,
+    /// Expand for the target triple, so code behind its cfgs shows up
+    #[clap(long, value_parser)]
+    target: Option<String>,
+    /// Build with this profile instead of `check` (or cargo expand's default with --path)
+    #[clap(long, value_parser)]
+    profile: Option<String>,
+    /// Toolchain to expand with, instead of the one in rust-toolchain(.toml) or nightly
+    #[clap(long, value_parser, conflicts_with = "path")]
+    toolchain: Option<String>,
+    /// Set RUSTC_BOOTSTRAP=1 so a stable compiler takes -Zunpretty, the default toolchain is
+    /// used unless one is picked
+    #[clap(long, action, conflicts_with = "path")]
+    rustc_bootstrap: bool,
     /// Struct macro to expand
     #[clap(short, long, value_parser)]
     structure: Option<String>,
     /// function to expand
     #[clap(short, long, value_parser)]
     function: Option<String>,
-    /// Path of the dir/file to watch, if specified then the proc macro output is logged
-    /// if a change is detected
+    /// Enum to expand
+    #[clap(short, long = "enum", value_parser)]
+    enumeration: Option<String>,
+    /// Union to expand
     #[clap(short, long, value_parser)]
-    watch: Option<String>,
+    union: Option<String>,
+    /// Type alias to expand
+    #[clap(long = "type", value_parser)]
+    type_alias: Option<String>,
+    /// Const item to expand
+    #[clap(short, long = "const", value_parser)]
+    constant: Option<String>,
+    /// Static item to expand
+    #[clap(long = "static", value_parser)]
+    static_item: Option<String>,
+    /// Trait definition to expand (use --trait-impl for the impls of a trait)
+    #[clap(long = "trait", value_parser)]
+    trait_def: Option<String>,
+    /// Colour the output with syntax highlighting
+    #[clap(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// Look into the items that parse when the expanded code does not, instead of failing
+    #[clap(long, action)]
+    recover: bool,
+    /// Format the code in process, or with rustfmt and the project's rustfmt.toml
+    #[clap(long, value_enum, default_value_t = FormatterChoice::Builtin)]
+    formatter: FormatterChoice,
+    /// Syntax highlighting theme, any of the themes bundled with bat
+    #[clap(long, value_parser)]
+    theme: Option<String>,
+    /// Print what the proc macros wrote to stderr, like their debug `eprintln!`s, to stderr.
+    /// Cargo's own output is left out
+    #[clap(long, action)]
+    show_stderr: bool,
+    /// Show the message and backtrace of a panicking proc macro (passes -Zproc-macro-backtrace
+    /// to rustc, not supported with --path)
+    #[clap(long, action, conflicts_with = "path")]
+    macro_backtrace: bool,
+    /// Always run cargo, instead of reusing the expansion of an unchanged tree from
+    /// target/croc-look
+    #[clap(long, action)]
+    no_cache: bool,
+    /// Paths of the dirs/files to watch, if specified then the proc macro output is logged
+    /// if a change is detected. Dirs are watched recursively, skipping target/ and
+    /// whatever .gitignore ignores. Without paths the current package, its path dependencies
+    /// and the proc macro crates of the workspace are watched
+    #[clap(short, long, value_parser, multiple_values = true, min_values = 0)]
+    watch: Option<Vec<String>>,
+}
+
+impl Args {
+    // item flags that are set, in the order `look` tries them
+    fn items(&self) -> impl Iterator<Item = (ItemKind, &str)> {
+        [
+            (ItemKind::Struct, &self.structure),
+            (ItemKind::Function, &self.function),
+            (ItemKind::Enum, &self.enumeration),
+            (ItemKind::Union, &self.union),
+            (ItemKind::TypeAlias, &self.type_alias),
+            (ItemKind::Const, &self.constant),
+            (ItemKind::Static, &self.static_item),
+            (ItemKind::Trait, &self.trait_def),
+        ]
+        .into_iter()
+        .filter_map(|(kind, ident)| ident.as_deref().map(|ident| (kind, ident)))
+    }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let ctx = Context::new(args);
-    let now = Instant::now();
-    let (code, ident) = look(&ctx)?;
 
-    if ctx.args.structure.is_none() && ctx.args.trait_impl.is_none() && ctx.args.function.is_none()
+    // watch mode needs a file it can read again
+    if args.watch.is_some() && args.input.as_deref() == Some("-") {
+        return Err(error_other(
+            "Cannot watch stdin, give --input a file".to_string(),
+        ));
+    }
+
+    let ctx = Context::new(args)?;
+
+    // in watch mode the item can be picked from the browser instead
+    if ctx.args.watch.is_none()
+        && ctx.args.trait_impl.is_none()
+        && ctx.args.inherent.is_none()
+        && ctx.args.method.is_none()
+        && ctx.args.items().next().is_none()
+        && ctx.args.query.is_empty()
     {
         return Err(error_other(
-            "No proc_macro, struct or function provided".to_string(),
+            "No trait or item to expand provided".to_string(),
         ));
     }
 
-    if let Some(ref file) = ctx.args.watch {
+    // cargo runs in a process group of its own, which the terminal's Ctrl-C does not reach
+    let interrupt_ctx = ctx.clone();
+    ctrlc::set_handler(move || {
+        if interrupt_ctx.args.watch.is_some() {
+            let _ = interrupt_ctx.send(Event::Interrupt);
+        } else {
+            interrupt_ctx.cancel();
+            process::exit(130);
+        }
+    })
+    .map_err(|e| error_other(format!("Cannot handle Ctrl-C: {}", e)))?;
+
+    // a typo would otherwise only show up as cargo's error after the first expansion
+    if ctx.args.package.is_some() {
+        Metadata::load(&ctx.args)?;
+    }
+
+    if let Some(ref paths) = ctx.args.watch {
+        let paths = if let (true, Some(input)) = (paths.is_empty(), &ctx.args.input) {
+            vec![input.clone()]
+        } else if paths.is_empty() {
+            Metadata::load(&ctx.args)?
+                .watch_paths()
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect()
+        } else {
+            paths.clone()
+        };
+        let paths = &roots(&paths);
+        // an unknown theme fails here, before the terminal is taken over
+        let highlighter = if ctx.args.color == ColorChoice::Never {
+            None
+        } else {
+            Some(Highlighter::new(ctx.args.theme.as_deref())?)
+        };
         enable_raw_mode()?;
 
         let mut stdout = io::stdout();
@@ -71,9 +264,19 @@ fn main() -> Result<()> {
         let mut terminal = Terminal::new(CrosstermBackend::new(stdout))?;
 
         // start listening for file changes
-        let watch_file = watch(file, &ctx);
+        let watch_handler = match watch(paths, &ctx) {
+            Ok(watch_handler) => watch_handler,
+            Err(err) => {
+                end(&mut terminal)?;
+                return Err(err);
+            }
+        };
         // setup UI components
-        let tui = CrocTui::new(code, ident);
+        let tui = CrocTui::new(
+            "Expanding the crate, this takes as long as a cargo check".to_string(),
+            "Starting".to_string(),
+            highlighter,
+        );
 
         // start watching all events
         if let Some(err) = watch_events(&ctx).err() {
@@ -82,33 +285,82 @@ fn main() -> Result<()> {
         };
 
         // render UI
-        if let Some(err) = terminal.draw(|e| tui.render(e, tui.components(now))).err() {
+        if let Some(err) = terminal.draw(|e| tui.render(e, tui.components())).err() {
             end(&mut terminal)?;
             return Err(err);
         }
 
-        match watch_file {
-            Ok(watch_handler) => {
-                // start acting on events
-                croc_start(tui, &ctx, watch_handler, file.to_string(), &mut terminal)?;
-            }
-            Err(err) => {
-                end(&mut terminal)?;
-                return Err(err);
-            }
+        // the first expansion runs in the background like every other one, cargo is only
+        // started once nothing above can fail
+        let worker = Worker::spawn(&ctx);
+        worker.request();
+
+        // start acting on events
+        if let Err(err) = croc_start(tui, &ctx, &worker, watch_handler, &mut terminal) {
+            // cargo runs in its own process group, it would outlive croc-look
+            worker.stop();
+            end(&mut terminal)?;
+            return Err(err);
         }
     } else {
         let now = Instant::now();
-        let loading = Loading::default();
+        // as a filter only the code goes to stdout
+        let filter = ctx.args.input.is_some();
 
-        loading.info("Running..");
-        loading.end();
+        if !filter {
+            let loading = Loading::default();
 
-        println!(
-            "\n{}\n\nFinished in {}ms",
-            look(&ctx)?.0,
-            now.elapsed().as_millis()
-        );
+            loading.info("Running..");
+            loading.end();
+        }
+
+        let (index, macro_output, skipped) = ctx.index().map_err(|e| {
+            // cancelled by Ctrl-C, the handler exits the same way
+            if e.kind() == ErrorKind::Interrupted {
+                process::exit(130);
+            }
+            // rustc's errors read best the way rustc prints them
+            if let Some(err) = diagnostics::compile_error(&e) {
+                if ctx.args.show_stderr {
+                    eprint!("{}", err.macro_output);
+                }
+                eprintln!("{}", err);
+                if let Some(panic) = &err.panic {
+                    println!("{}", panic);
+                }
+                return error_other("Cannot expand code, it does not compile".to_string());
+            }
+            if let Some(err) = parse::parse_error(&e) {
+                eprintln!("{}", err);
+                return error_other(
+                    "Cannot parse the expanded code, --recover looks into the items that parse"
+                        .to_string(),
+                );
+            }
+            e
+        })?;
+        if ctx.args.show_stderr {
+            eprint!("{}", macro_output);
+        }
+        for err in &skipped {
+            eprintln!("{}\nnote: the item is left out\n", err);
+        }
+        let code = look(&ctx, &index)?.0;
+
+        if ctx.args.color.enabled() {
+            let highlighter = Highlighter::new(ctx.args.theme.as_deref())?;
+
+            if filter {
+                return highlighter.print(&code);
+            }
+            println!();
+            highlighter.print(&code)?;
+            println!("\n\nFinished in {}ms", now.elapsed().as_millis());
+        } else if filter {
+            print!("{}", code);
+        } else {
+            println!("\n{}\n\nFinished in {}ms", code, now.elapsed().as_millis());
+        }
     }
 
     Ok(())
@@ -117,54 +369,140 @@ fn main() -> Result<()> {
 fn croc_start(
     mut tui: CrocTui,
     ctx: &Context,
-    mut watch_handler: Hotwatch,
-    file: String,
+    worker: &Worker,
+    watch_handler: Hotwatch,
     terminal: &mut CrocTerminal,
 ) -> Result<()> {
     loop {
         match ctx.main_channel.1.recv() {
-            Ok(Event::Interrupt) => {
-                watch_handler
-                    .unwatch(file)
-                    .map_err(|e| error_other(format!("Unwatching error: {}", e)))?;
+            // typing into the item filter
+            Ok(Event::KeyChar(c)) if tui.browser.filtering => {
+                tui.browser.push(c);
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::Interrupt | Event::KeyChar('q')) => {
+                worker.stop();
+                // unwatching one by one fails for dirs whose subdirs came and went (target/),
+                // dropping the watcher stops all of it
+                drop(watch_handler);
                 break;
             }
-            Ok(Event::FileUpdate) => {
-                let now = Instant::now();
-                tui.code_block(look(ctx)?.0);
+            Ok(Event::FileUpdate | Event::KeyChar('r')) => worker.request(),
+            Ok(Event::ExpansionStarted) => {
+                tui.running.get_or_insert_with(Instant::now);
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::ExpansionFinished(expansion)) => {
+                match expansion {
+                    Ok(expansion) => show(ctx, &mut tui, expansion)?,
+                    // mid edit errors are normal, the next change will likely fix them
+                    Err(err) => tui.failed(&err),
+                }
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::Tick) if tui.running.is_some() => {
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::Tick) => (),
+            Ok(Event::KeyChar('/')) => {
+                tui.focus = Focus::Browser;
+                tui.browser.filtering = true;
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::KeyChar('d')) => {
+                tui.toggle_diff();
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::KeyChar(_)) => (),
+            Ok(Event::KeyTab) => {
+                tui.toggle_focus();
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::KeyEnter) => {
+                if tui.browser.filtering {
+                    tui.browser.filtering = false;
+                } else if tui.focus == Focus::Browser {
+                    if let Some(entry) = tui.browser.open() {
+                        let msg = format!("Browsing {}", entry.label);
+                        let code = entry.code.clone();
+                        tui.open(ctx.format_code(code), msg);
+                    }
+                }
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::KeyEsc) => {
+                if tui.browser.filtering || !tui.browser.filter.is_empty() {
+                    tui.browser.clear_filter();
+                } else {
+                    tui.focus = Focus::Segment;
+                }
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::KeyBackspace) => {
+                tui.browser.pop();
 
-                terminal.draw(|e| tui.render(e, tui.components(now)))?;
+                terminal.draw(|e| tui.render(e, tui.components()))?;
             }
             Ok(Event::Resize) => {
-                let now = Instant::now();
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::KeyArrowUp) if tui.focus == Focus::Browser => {
+                tui.browser.select_prev();
+
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(Event::KeyArrowDown) if tui.focus == Focus::Browser => {
+                tui.browser.select_next();
 
-                terminal.draw(|e| tui.render(e, tui.components(now)))?;
+                terminal.draw(|e| tui.render(e, tui.components()))?;
+            }
+            Ok(
+                event @ (Event::KeyArrowUp
+                | Event::KeyArrowDown
+                | Event::KeyArrowRight
+                | Event::KeyArrowLeft),
+            ) if tui.pane_scroll().is_some() => {
+                terminal.draw(|e| {
+                    let width = e.size().width;
+                    if let Some(scroll) = tui.pane_scroll() {
+                        match event {
+                            Event::KeyArrowUp => scroll.scroll_up(),
+                            Event::KeyArrowDown => scroll.scroll_down(),
+                            Event::KeyArrowRight => scroll.scroll_right(width),
+                            _ => scroll.scroll_left(),
+                        }
+                    }
+                    tui.render(e, tui.components())
+                })?;
             }
             Ok(Event::KeyArrowUp) => {
-                let now = Instant::now();
                 tui.scroll.scroll_up();
 
-                terminal.draw(|e| tui.scroll_code_block_and_render(e, now))?;
+                terminal.draw(|e| tui.scroll_code_block_and_render(e))?;
             }
             Ok(Event::KeyArrowDown) => {
-                let now = Instant::now();
                 tui.scroll.scroll_down();
 
-                terminal.draw(|e| tui.scroll_code_block_and_render(e, now))?;
+                terminal.draw(|e| tui.scroll_code_block_and_render(e))?;
             }
             Ok(Event::KeyArrowRight) => {
-                let now = Instant::now();
-
                 terminal.draw(|e| {
                     tui.scroll.scroll_right(e.size().width);
-                    tui.scroll_code_block_and_render(e, now)
+                    tui.scroll_code_block_and_render(e)
                 })?;
             }
             Ok(Event::KeyArrowLeft) => {
-                let now = Instant::now();
                 tui.scroll.scroll_left();
 
-                terminal.draw(|e| tui.scroll_code_block_and_render(e, now))?;
+                terminal.draw(|e| tui.scroll_code_block_and_render(e))?;
             }
             Err(e) => return Err(error_other(format!("Reciving error: {}", e))),
         }
@@ -174,29 +512,118 @@ fn croc_start(
     Ok(())
 }
 
-fn look(ctx: &Context) -> Result<(String, String)> {
+// show a finished expansion, the item picked in the browser takes over from the flags
+fn show(ctx: &Context, tui: &mut CrocTui, expansion: Expansion) -> Result<()> {
+    let first = tui.took.is_none();
+
+    tui.running = None;
+    tui.took = Some(expansion.took);
+    tui.skipped = expansion.skipped.len();
+    tui.recovered();
+    tui.macro_output(expansion.macro_output);
+    tui.browser.update(expansion.entries);
+
+    if let Some(entry) = tui.browser.current_entry() {
+        tui.code_block(ctx.format_code(entry.code.clone()));
+    } else if first {
+        // nothing to diff against the placeholder
+        tui.open(expansion.code, expansion.msg);
+    } else {
+        tui.code_block(expansion.code);
+    }
+
+    Ok(())
+}
+
+// like `look` but nothing matching is not an error, the item browser can be used instead
+fn look_or_browse(ctx: &Context, index: &ItemIndex) -> Result<(String, String)> {
+    match look(ctx, index) {
+        Ok((code, msg)) if !msg.is_empty() => Ok((code, msg)),
+        Ok(_) => Ok((
+            "Pick an item to expand, tab to switch to the item list and / to filter it".to_string(),
+            "Nothing to expand".to_string(),
+        )),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok((
+            "Nothing matched, tab to switch to the item list and / to filter it".to_string(),
+            "Nothing found".to_string(),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+fn look(ctx: &Context, index: &ItemIndex) -> Result<(String, String)> {
+    let mut blocks = Vec::new();
+
     if let Some(ident) = &ctx.args.trait_impl {
-        let msg: String;
-        if let Some(struct_ident) = &ctx.args.impl_for {
-            msg = format!("Expanding trait: {} for {}", ident, struct_ident);
+        let prefix = if ctx.args.all {
+            "Expanding all impls of trait"
+        } else {
+            "Expanding trait"
+        };
+        let msg = ctx.args.impl_for.as_ref().map_or_else(
+            || format!("{}: {}", prefix, ident),
+            |struct_ident| format!("{}: {} for {}", prefix, ident, struct_ident),
+        );
+
+        if ctx.args.all {
+            blocks.push((
+                ctx.c_trait_all(index, ident, ctx.args.impl_for.as_deref())?,
+                msg,
+            ));
         } else {
-            msg = format!("Expanding trait: {}", ident);
+            blocks.push((
+                ctx.c_trait(index, ident, ctx.args.impl_for.as_deref())?,
+                msg,
+            ));
         }
-        return Ok((ctx.c_trait(ident, ctx.args.impl_for.as_deref())?, msg));
     }
 
-    if let Some(ident) = &ctx.args.structure {
-        return Ok((ctx.c_struct(ident)?, format!("Expanding struct: {}", ident)));
+    if let Some(ty) = &ctx.args.inherent {
+        blocks.push((
+            ctx.c_inherent(index, ty)?,
+            format!("Expanding inherent impls: {}", ty),
+        ));
     }
 
-    if let Some(ident) = &ctx.args.function {
-        return Ok((ctx.c_func(ident)?, format!("Expanding function: {}", ident)));
+    if let Some(selector) = &ctx.args.method {
+        blocks.push((
+            ctx.c_method(index, selector)?,
+            format!("Expanding method: {}", selector),
+        ));
     }
 
-    // Did not find anything
-    ctx.send(Event::Interrupt)?;
+    for (kind, ident) in ctx.args.items() {
+        blocks.push((
+            ctx.c_item(index, kind, ident)?,
+            format!("Expanding {}: {}", kind.description(), ident),
+        ));
+    }
+
+    for query in &ctx.args.query {
+        let query = Query::parse(query)?;
+        blocks.push((ctx.c_query(index, &query)?, format!("Expanding {}", query)));
+    }
 
-    Ok((String::new(), String::new()))
+    match blocks.len() {
+        // Nothing to look for
+        0 => Ok((String::new(), String::new())),
+        1 => Ok(blocks.remove(0)),
+        // several targets, give each block a header
+        _ => {
+            let code = blocks
+                .iter()
+                .map(|(code, msg)| format!("// {}\n{}", msg, code))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let msg = blocks
+                .iter()
+                .map(|(_, msg)| msg.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            Ok((code, msg))
+        }
+    }
 }
 
 fn end(terminal: &mut CrocTerminal) -> Result<()> {
@@ -208,5 +635,93 @@ fn end(terminal: &mut CrocTerminal) -> Result<()> {
 }
 
 fn error_other(msg: String) -> Error {
-    Error::new(ErrorKind::Other, msg)
+    Error::other(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expander::Expander;
+
+    use std::iter;
+
+    const EXPANDED: &str = "pub struct Foo {
+    a: u8,
+}
+mod net {
+    pub struct Frame;
+}
+fn first(y: Option<u8>) -> u8 {
+    let Some(x) = y else { return 0 };
+    x
+}
+fn broken( {}
+";
+
+    // Always expands to the same code, what a crate that never changes would do
+    struct Stub(&'static str);
+
+    impl Expander for Stub {
+        fn expand(&self, _ctx: &Context) -> Result<(String, String)> {
+            Ok((self.0.to_string(), "printed by a macro\n".to_string()))
+        }
+    }
+
+    fn context(flags: &[&str]) -> Context {
+        let args = Args::parse_from(iter::once(&"croc-look").chain(flags));
+
+        Context::with_expander(args, Box::new(Stub(EXPANDED)))
+    }
+
+    #[test]
+    fn look_through_expander() {
+        let ctx = context(&["--recover", "-s", "Foo", "-q", "struct Frame"]);
+        let (index, macro_output, skipped) = ctx.index().unwrap();
+
+        assert_eq!(macro_output, "printed by a macro\n");
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].line, 11);
+
+        let (code, msg) = look(&ctx, &index).unwrap();
+        assert_eq!(
+            code,
+            "// Expanding struct: Foo\npub struct Foo {\n    a: u8,\n}\n\n\
+             // Expanding struct Frame\n// struct Frame in crate::net\npub struct Frame;\n"
+        );
+        assert!(msg.starts_with("Expanding struct: Foo"));
+    }
+
+    // prettyplease cannot print let-else, the item is shown as it is
+    #[test]
+    fn look_unformatted() {
+        let ctx = context(&["--recover", "-f", "first"]);
+        let (index, _, _) = ctx.index().unwrap();
+        let (code, _) = look(&ctx, &index).unwrap();
+
+        assert!(code.contains("let Some (x) = y else { return 0 } ;"));
+    }
+
+    #[test]
+    fn look_without_a_match() {
+        let ctx = context(&["--recover", "-s", "Bar"]);
+        let (index, _, _) = ctx.index().unwrap();
+
+        assert_eq!(look(&ctx, &index).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+
+    // cargo expand has no way to pass -Zproc-macro-backtrace on
+    #[test]
+    fn macro_backtrace_needs_rustc() {
+        let args = ["croc-look", "--path", "foo", "--macro-backtrace"];
+
+        assert!(Args::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn index_fails_where_it_does_not_parse() {
+        let ctx = context(&["-s", "Foo"]);
+        let err = ctx.index().err().unwrap();
+
+        assert_eq!(parse::parse_error(&err).map(|e| e.line), Some(11));
+    }
 }
@@ -0,0 +1,204 @@
+use crate::{error_other, Args};
+
+use std::env;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde_json::Value;
+
+// The packages of the workspace as reported by `cargo metadata`
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_root: PathBuf,
+    pub target_directory: PathBuf,
+    // --package and --manifest-path, they decide which package is the current one
+    package: Option<String>,
+    manifest_path: Option<PathBuf>,
+}
+
+pub struct Package {
+    pub name: String,
+    pub manifest_path: PathBuf,
+    pub edition: String,
+    pub proc_macro: bool,
+    // dirs holding the root file of each target, usually just src/
+    pub src_dirs: Vec<PathBuf>,
+    // dirs of the dependencies pulled in with `path = "..."`
+    pub path_deps: Vec<PathBuf>,
+}
+
+impl Metadata {
+    // fails if --package is not a member of the workspace
+    pub fn load(args: &Args) -> Result<Self> {
+        let mut cmd = Command::new("cargo");
+        cmd.arg("metadata")
+            .arg("--format-version")
+            .arg("1")
+            .arg("--no-deps");
+        if let Some(manifest_path) = &args.manifest_path {
+            cmd.arg("--manifest-path").arg(manifest_path);
+        }
+        let output = cmd.output()?;
+
+        if !output.status.success() {
+            return Err(error_other(format!(
+                "Cannot read cargo metadata, stderr: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        let json: Value = serde_json::from_slice(&output.stdout)
+            .map_err(|e| error_other(format!("Cannot parse cargo metadata: {}", e)))?;
+        let packages: Vec<Package> = json["packages"]
+            .as_array()
+            .map(|packages| packages.iter().filter_map(Package::from_json).collect())
+            .unwrap_or_default();
+
+        if let Some(package) = &args.package {
+            if !packages.iter().any(|e| &e.name == package) {
+                return Err(error_other(format!(
+                    "No package `{}` in the workspace, its packages are: {}",
+                    package,
+                    packages
+                        .iter()
+                        .map(|e| e.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )));
+            }
+        }
+
+        let path = |key: &str| {
+            json[key]
+                .as_str()
+                .map(PathBuf::from)
+                .ok_or_else(|| error_other(format!("No {} in cargo metadata", key)))
+        };
+
+        Ok(Self {
+            packages,
+            workspace_root: path("workspace_root")?,
+            target_directory: path("target_directory")?,
+            package: args.package.clone(),
+            manifest_path: args
+                .manifest_path
+                .as_ref()
+                .and_then(|e| Path::new(e).canonicalize().ok()),
+        })
+    }
+
+    // the package cargo builds, picked with --package or by the dir of the manifest or the
+    // current dir, every package at a virtual workspace root
+    pub fn current(&self) -> Vec<&Package> {
+        if let Some(name) = &self.package {
+            return self.packages.iter().filter(|e| &e.name == name).collect();
+        }
+
+        let dir = self.manifest_path.as_ref().map_or_else(
+            || {
+                env::current_dir()
+                    .and_then(|e| e.canonicalize())
+                    .unwrap_or_default()
+            },
+            |manifest_path| {
+                manifest_path
+                    .parent()
+                    .unwrap_or(manifest_path)
+                    .to_path_buf()
+            },
+        );
+        let containing = self
+            .packages
+            .iter()
+            .filter(|package| dir.starts_with(package.dir()))
+            .max_by_key(|package| package.dir().components().count());
+
+        containing.map_or_else(|| self.packages.iter().collect(), |package| vec![package])
+    }
+
+    // what --watch without paths watches: the sources and manifests of the current package,
+    // the path dependencies it pulls in and every proc macro crate of the workspace
+    pub fn watch_paths(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut pending: Vec<PathBuf> = self
+            .current()
+            .into_iter()
+            .chain(self.packages.iter().filter(|package| package.proc_macro))
+            .map(|package| package.dir().to_path_buf())
+            .collect();
+
+        // path dependencies of path dependencies, as far as they are workspace members
+        while let Some(dir) = pending.pop() {
+            if dirs.contains(&dir) {
+                continue;
+            }
+
+            if let Some(package) = self.package_in(&dir) {
+                pending.extend(package.path_deps.iter().cloned());
+            }
+            dirs.push(dir);
+        }
+
+        let mut paths = Vec::new();
+        for dir in dirs {
+            match self.package_in(&dir) {
+                Some(package) => paths.extend(package.src_dirs.iter().cloned()),
+                // not in the workspace, assume the usual layout
+                None => paths.push(dir.join("src")),
+            }
+            paths.push(dir.join("Cargo.toml"));
+        }
+
+        paths.retain(|path| path.exists());
+        paths.dedup();
+        paths
+    }
+
+    fn package_in(&self, dir: &Path) -> Option<&Package> {
+        self.packages.iter().find(|package| package.dir() == dir)
+    }
+}
+
+impl Package {
+    fn from_json(json: &Value) -> Option<Self> {
+        let targets = json["targets"].as_array()?;
+        let proc_macro = targets.iter().any(|target| {
+            target["kind"]
+                .as_array()
+                .is_some_and(|kinds| kinds.iter().any(|kind| kind == "proc-macro"))
+        });
+        let mut src_dirs = targets
+            .iter()
+            .filter_map(|target| target["src_path"].as_str())
+            .filter_map(|path| Path::new(path).parent().map(Path::to_path_buf))
+            .collect::<Vec<_>>();
+        // src/ covers src/bin/ and friends
+        src_dirs.sort();
+        src_dirs.dedup_by(|nested, parent| nested.starts_with(parent));
+
+        let path_deps = json["dependencies"]
+            .as_array()
+            .map(|dependencies| {
+                dependencies
+                    .iter()
+                    .filter_map(|dependency| dependency["path"].as_str())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(Self {
+            name: json["name"].as_str()?.to_string(),
+            manifest_path: PathBuf::from(json["manifest_path"].as_str()?),
+            edition: json["edition"].as_str()?.to_string(),
+            proc_macro,
+            src_dirs,
+            path_deps,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.manifest_path.parent().unwrap_or(&self.manifest_path)
+    }
+}
@@ -0,0 +1,229 @@
+use std::error;
+use std::fmt;
+use std::io::{Error, Result};
+
+use syn::{File, Item};
+
+// rustc and cargo expand print nested items indented by this much
+const INDENT: usize = 4;
+
+// The expanded code does not parse, with where in it
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    // 1 based, like rustc's
+    pub line: usize,
+    pub column: usize,
+    // the offending line of the expanded code
+    pub source: String,
+}
+
+impl ParseError {
+    fn new(err: &syn::Error, code: &str) -> Self {
+        let start = err.span().start();
+
+        Self {
+            message: err.to_string(),
+            line: start.line.max(1),
+            column: start.column + 1,
+            source: code
+                .lines()
+                .nth(start.line.saturating_sub(1))
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
+    // the error of a chunk, moved to where the chunk is in the whole file
+    fn offset(mut self, lines: usize, columns: usize) -> Self {
+        self.line += lines;
+        self.column += columns;
+        self.source.insert_str(0, &" ".repeat(columns));
+        self
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(
+            f,
+            "{}--> expanded code:{}:{}",
+            gutter, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source)?;
+        write!(f, "{} | {}^", gutter, " ".repeat(self.column - 1))
+    }
+}
+
+impl error::Error for ParseError {}
+
+// parse the expanded code, failing with where it does not parse
+pub fn parse(code: &str) -> Result<File> {
+    forget_spans();
+
+    syn::parse_file(code).map_err(|e| Error::other(ParseError::new(&e, code)))
+}
+
+// best effort: every top level item is parsed on its own and the ones that do not parse are
+// left out, a module that does not parse has its items recovered the same way
+pub fn recover(code: &str) -> (File, Vec<ParseError>) {
+    forget_spans();
+
+    syn::parse_file(code).map_or_else(|_| recover_chunks(code), |file| (file, Vec::new()))
+}
+
+fn recover_chunks(code: &str) -> (File, Vec<ParseError>) {
+    let mut file = File {
+        shebang: None,
+        attrs: Vec::new(),
+        items: Vec::new(),
+    };
+    let mut errors = Vec::new();
+
+    for (start, chunk) in chunks(code) {
+        match syn::parse_file(&chunk) {
+            Ok(parsed) => {
+                file.attrs.extend(parsed.attrs);
+                file.items.extend(parsed.items);
+            }
+            Err(e) => match recover_mod(&chunk) {
+                Some((item, mod_errors)) => {
+                    file.items.push(item);
+                    errors.extend(mod_errors.into_iter().map(|e| e.offset(start, 0)));
+                }
+                None => errors.push(ParseError::new(&e, &chunk).offset(start, 0)),
+            },
+        }
+    }
+
+    (file, errors)
+}
+
+// `mod name {` with the items indented below it and `}` on the last line
+fn recover_mod(chunk: &str) -> Option<(Item, Vec<ParseError>)> {
+    let lines = chunk.lines().collect::<Vec<_>>();
+    let header = lines
+        .iter()
+        .position(|line| !line.starts_with('#') && line.trim_end().ends_with('{'))?;
+    let (last, body) = lines[header + 1..].split_last()?;
+
+    if *last != "}" {
+        return None;
+    }
+
+    let mut item: Item = syn::parse_str(&format!("{} }}", lines[..=header].join("\n"))).ok()?;
+    let Item::Mod(module) = &mut item else {
+        return None;
+    };
+
+    let body = body
+        .iter()
+        .map(|line| line.get(INDENT..).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let (recovered, errors) = recover_chunks(&body);
+    if let Some((_, items)) = &mut module.content {
+        items.extend(recovered.items);
+    }
+
+    let errors = errors
+        .into_iter()
+        .map(|e| e.offset(header + 1, INDENT))
+        .collect();
+
+    Some((item, errors))
+}
+
+// top level items start at the beginning of a line, along with their attributes and doc
+// comments, closing brackets end the item above. Paired with the line the chunk starts at
+fn chunks(code: &str) -> Vec<(usize, String)> {
+    let mut chunks: Vec<(usize, String)> = Vec::new();
+    let mut attributes = false;
+
+    for (i, line) in code.lines().enumerate() {
+        let starts_item = line.starts_with(|c: char| !c.is_whitespace() && !")]}".contains(c));
+
+        match chunks.last_mut() {
+            Some((_, chunk)) if !starts_item || attributes => {
+                chunk.push('\n');
+                chunk.push_str(line);
+            }
+            _ => chunks.push((i, line.to_string())),
+        }
+        if starts_item {
+            attributes = line.starts_with("#[") || line.starts_with("///");
+        }
+    }
+
+    chunks
+}
+
+// the spans of earlier parses are never looked at again, without this every expansion
+// parsed on a thread stays in memory for line and column lookups
+pub fn forget_spans() {
+    proc_macro2::extra::invalidate_current_thread_spans();
+}
+
+// where the expanded code does not parse, if that is why it failed
+pub fn parse_error(err: &Error) -> Option<&ParseError> {
+    err.get_ref()?.downcast_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_start_at_items() {
+        let code = "#![feature(prelude_import)]\n#[derive(Debug)]\npub struct Foo {\n    a: u8,\n}\n/// doc\nfn main() {}\n";
+
+        assert_eq!(
+            chunks(code),
+            [
+                (0, "#![feature(prelude_import)]".to_string()),
+                (
+                    1,
+                    "#[derive(Debug)]\npub struct Foo {\n    a: u8,\n}".to_string()
+                ),
+                (5, "/// doc\nfn main() {}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn recover_mod_items() {
+        let chunk = "mod a {\n    fn ok() {}\n    fn broken( {}\n}";
+        let (item, errors) = recover_mod(chunk).unwrap();
+
+        let Item::Mod(module) = item else {
+            panic!("not a module");
+        };
+        assert_eq!(module.content.map(|(_, items)| items.len()), Some(1));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+        assert_eq!(errors[0].source, "    fn broken( {}");
+
+        assert!(recover_mod("mod a {\n    fn broken( {}").is_none());
+        assert!(recover_mod("fn broken( {\n}").is_none());
+    }
+
+    #[test]
+    fn recover_skips_items() {
+        let code =
+            "struct Ok;\nfn broken( {}\nmod a {\n    struct Ok;\n    impl {}\n}\nfn ok() {}\n";
+        let (file, errors) = recover(code);
+
+        assert_eq!(file.items.len(), 3);
+        let lines = errors
+            .iter()
+            .map(|e| (e.line, e.column))
+            .collect::<Vec<_>>();
+        // the unclosed `(` and the `{` where `impl` wants a type
+        assert_eq!(lines, [(2, 10), (5, 10)]);
+        assert!(parse(code).is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use crate::error_other;
+use crate::locate::{type_string, IndexedItem, ItemKind};
+
+use std::fmt;
+use std::io::Result;
+
+use syn::{Path, Type};
+
+// A selector like `impl Serialize for Config in config`, `fn main` or `struct *Request`
+//
+// query := "impl" TRAIT ["for" TYPE] ["in" MODULE]
+//        | "method" TYPE::ITEM
+//        | KIND NAME ["in" MODULE]
+//
+// TRAIT, NAME and MODULE can be qualified with `::` and use `*` and `?` globs, TYPE is either a
+// glob on the written type or a type pattern as taken by --impl-for
+pub struct Query {
+    text: String,
+    pub target: Target,
+}
+
+pub enum Target {
+    // trait impls of TRAIT and inherent impls of a type named TRAIT
+    Impl {
+        name: Vec<String>,
+        impl_for: Option<TypeMatcher>,
+        module: Option<Vec<String>>,
+    },
+    Method(String),
+    Item {
+        kind: ItemKind,
+        name: Vec<String>,
+        module: Option<Vec<String>>,
+    },
+}
+
+pub enum TypeMatcher {
+    Glob(String),
+    Pattern(Box<Type>),
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self> {
+        let invalid = |msg: &str| error_other(format!("Invalid query `{}`: {}", text, msg));
+        let mut words = text.split_whitespace();
+
+        let keyword = words.next().ok_or_else(|| invalid("empty query"))?;
+        let rest = words.collect::<Vec<_>>();
+
+        let target = match keyword {
+            "method" => match rest.as_slice() {
+                [] => return Err(invalid("expected `method Type::item`")),
+                selector => Target::Method(selector.join(" ")),
+            },
+            "impl" => {
+                let (head, module) = split_clause(&rest, "in");
+                let (name, impl_for) = split_clause(head, "for");
+
+                Target::Impl {
+                    name: qualified(name).ok_or_else(|| invalid("expected a trait or type"))?,
+                    impl_for: impl_for
+                        .map(|ty| TypeMatcher::parse(&ty.join(" ")))
+                        .transpose()
+                        .map_err(|e| invalid(&e.to_string()))?,
+                    module: module.map(module_path),
+                }
+            }
+            keyword => {
+                let kind = item_kind(keyword).ok_or_else(|| {
+                    invalid("expected one of impl, method, struct, enum, union, type, const, static, trait, fn or mod")
+                })?;
+                let (name, module) = split_clause(&rest, "in");
+
+                Target::Item {
+                    kind,
+                    name: qualified(name).ok_or_else(|| invalid("expected a name"))?,
+                    module: module.map(module_path),
+                }
+            }
+        };
+
+        Ok(Self {
+            text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+            target,
+        })
+    }
+
+    pub fn matches(&self, item: &IndexedItem) -> bool {
+        match &self.target {
+            Target::Impl {
+                name,
+                impl_for,
+                module,
+            } => {
+                let name_matches = match (item.kind, item.self_ty()) {
+                    (ItemKind::TraitImpl, _) => item
+                        .trait_path()
+                        .is_some_and(|path| globs_match(name, &path_idents(path), false)),
+                    // inherent impls have no `for`
+                    (ItemKind::InherentImpl, Some(Type::Path(ty))) if impl_for.is_none() => {
+                        globs_match(name, &path_idents(&ty.path), false)
+                    }
+                    _ => false,
+                };
+
+                name_matches
+                    && impl_for.as_ref().is_none_or(|ty| ty.matches(item))
+                    && module_matches(module.as_deref(), item)
+            }
+            Target::Item { kind, name, module } => {
+                let (modules, name) = name.split_at(name.len() - 1);
+                let (anchored, modules) = strip_crate(modules);
+
+                item.kind == *kind
+                    && glob_match(&name[0], &item.name)
+                    && globs_match(modules, &item.module_path, anchored)
+                    && module_matches(module.as_deref(), item)
+            }
+            Target::Method(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl TypeMatcher {
+    fn parse(ty: &str) -> syn::Result<Self> {
+        if ty.contains(['*', '?']) {
+            return Ok(Self::Glob(ty.split_whitespace().collect()));
+        }
+
+        syn::parse_str(ty).map(|ty| Self::Pattern(Box::new(ty)))
+    }
+
+    fn matches(&self, item: &IndexedItem) -> bool {
+        match self {
+            Self::Glob(glob) => item
+                .self_ty()
+                .is_some_and(|ty| glob_match(glob, &type_string(ty).replace(' ', ""))),
+            Self::Pattern(pattern) => item.self_ty_matches(pattern),
+        }
+    }
+}
+
+// split the words at `keyword`, returning what comes before and after it
+fn split_clause<'a, 'b>(
+    words: &'a [&'b str],
+    keyword: &str,
+) -> (&'a [&'b str], Option<&'a [&'b str]>) {
+    words
+        .iter()
+        .position(|word| *word == keyword)
+        .map_or((words, None), |index| {
+            (&words[..index], Some(&words[index + 1..]))
+        })
+}
+
+// `net::codec::Frame` as its segments, the name has to be a single word
+fn qualified(words: &[&str]) -> Option<Vec<String>> {
+    match words {
+        [word] => Some(word.split("::").map(String::from).collect()),
+        _ => None,
+    }
+}
+
+fn module_path(words: &[&str]) -> Vec<String> {
+    words
+        .concat()
+        .split("::")
+        .filter(|segment| !segment.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+const fn item_kind(keyword: &str) -> Option<ItemKind> {
+    let kind = match keyword.as_bytes() {
+        b"struct" => ItemKind::Struct,
+        b"enum" => ItemKind::Enum,
+        b"union" => ItemKind::Union,
+        b"type" => ItemKind::TypeAlias,
+        b"const" => ItemKind::Const,
+        b"static" => ItemKind::Static,
+        b"trait" => ItemKind::Trait,
+        b"fn" => ItemKind::Function,
+        b"mod" => ItemKind::Module,
+        _ => return None,
+    };
+
+    Some(kind)
+}
+
+fn module_matches(module: Option<&[String]>, item: &IndexedItem) -> bool {
+    module.is_none_or(|module| {
+        let (anchored, module) = strip_crate(module);
+        globs_match(module, &item.module_path, anchored)
+    })
+}
+
+fn strip_crate(segments: &[String]) -> (bool, &[String]) {
+    match segments.split_first() {
+        Some((first, rest)) if first == "crate" => (true, rest),
+        _ => (false, segments),
+    }
+}
+
+fn path_idents(path: &Path) -> Vec<String> {
+    path.segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect()
+}
+
+// the globs have to match a suffix of the segments, or all of them if anchored
+fn globs_match(globs: &[String], segments: &[String], anchored: bool) -> bool {
+    if globs.len() > segments.len() || (anchored && globs.len() != segments.len()) {
+        return false;
+    }
+
+    globs
+        .iter()
+        .zip(&segments[segments.len() - globs.len()..])
+        .all(|(glob, segment)| glob_match(glob, segment))
+}
+
+// `*` matches any number of characters and `?` exactly one
+fn glob_match(glob: &str, text: &str) -> bool {
+    fn inner(glob: &[char], text: &[char]) -> bool {
+        match glob.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len()).any(|skip| inner(rest, &text[skip..])),
+            Some(('?', rest)) => !text.is_empty() && inner(rest, &text[1..]),
+            Some((c, rest)) => text.first() == Some(c) && inner(rest, &text[1..]),
+        }
+    }
+
+    inner(
+        &glob.chars().collect::<Vec<_>>(),
+        &text.chars().collect::<Vec<_>>(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locate::ItemIndex;
+
+    const SOURCE: &str = r#"
+pub struct GetRequest;
+pub struct Response;
+impl Clone for GetRequest {
+    fn clone(&self) -> Self {
+        GetRequest
+    }
+}
+mod net {
+    pub struct PutRequest;
+    impl Clone for PutRequest {
+        fn clone(&self) -> Self {
+            PutRequest
+        }
+    }
+    impl PutRequest {
+        fn send(&self) {}
+    }
+}
+"#;
+
+    // what the query matches in SOURCE
+    fn matches(query: &str) -> Vec<String> {
+        let index = ItemIndex::new(&syn::parse_file(SOURCE).unwrap());
+        let query = Query::parse(query).unwrap();
+
+        index
+            .items()
+            .filter(|item| query.matches(item))
+            .map(IndexedItem::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn parse_targets() {
+        let query = Query::parse("impl  Serialize for Config in config").unwrap();
+        assert_eq!(query.to_string(), "impl Serialize for Config in config");
+        assert!(matches!(
+            query.target,
+            Target::Impl {
+                ref name,
+                impl_for: Some(TypeMatcher::Pattern(_)),
+                module: Some(ref module),
+            } if name == &["Serialize"] && module == &["config"]
+        ));
+
+        let query = Query::parse("impl Debug for Wrapper<*>").unwrap();
+        assert!(matches!(
+            query.target,
+            Target::Impl {
+                impl_for: Some(TypeMatcher::Glob(ref glob)),
+                module: None,
+                ..
+            } if glob == "Wrapper<*>"
+        ));
+
+        let query = Query::parse("struct net::*Request").unwrap();
+        assert!(matches!(
+            query.target,
+            Target::Item {
+                kind: ItemKind::Struct,
+                ref name,
+                module: None,
+            } if name == &["net", "*Request"]
+        ));
+
+        let query = Query::parse("method <Foo as Clone>::clone").unwrap();
+        assert!(matches!(
+            query.target,
+            Target::Method(ref selector) if selector == "<Foo as Clone>::clone"
+        ));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for query in [
+            "",
+            "method",
+            "impl",
+            "impl Clone for",
+            "widget Foo",
+            "fn a b",
+        ] {
+            assert!(Query::parse(query).is_err(), "`{}` parsed", query);
+        }
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("*Request", "GetRequest"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("Get?", "Gets"));
+        assert!(!glob_match("Get?", "Get"));
+        assert!(glob_match("a*b*c", "aXbYc"));
+        assert!(!glob_match("a*b", "ac"));
+        assert!(!glob_match("Request", "GetRequest"));
+    }
+
+    #[test]
+    fn matches_items() {
+        assert_eq!(
+            matches("struct *Request"),
+            [
+                "struct GetRequest in crate",
+                "struct PutRequest in crate::net"
+            ]
+        );
+        assert_eq!(
+            matches("struct *Request in crate"),
+            ["struct GetRequest in crate"]
+        );
+        assert_eq!(
+            matches("struct crate::net::*"),
+            ["struct PutRequest in crate::net"]
+        );
+        assert_eq!(
+            matches("impl Clone for net::PutRequest"),
+            ["impl Clone for PutRequest in crate::net"]
+        );
+        assert_eq!(
+            matches("impl PutRequest"),
+            ["impl PutRequest in crate::net"]
+        );
+        assert_eq!(matches("fn send"), ["fn PutRequest::send in crate::net"]);
+        assert!(matches("method PutRequest::send").is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{error_other, Args};
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Toolchain files rustup looks for, the first one found going up from the package wins
+const TOOLCHAIN_FILES: [&str; 2] = ["rust-toolchain.toml", "rust-toolchain"];
+
+// The compiler the expansion runs with, -Zunpretty needs a nightly one or RUSTC_BOOTSTRAP
+pub struct Toolchain {
+    // run through `rustup run <name>`, `None` runs whatever cargo is on the PATH
+    name: Option<String>,
+    // the toolchain file it came from
+    file: Option<PathBuf>,
+    bootstrap: bool,
+}
+
+impl Toolchain {
+    // --toolchain, else the project's toolchain file, else nightly. With --rustc-bootstrap
+    // the default toolchain is fine as it is
+    pub fn new(args: &Args) -> Result<Self> {
+        let rustup = Command::new("rustup")
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok();
+
+        let file = args
+            .toolchain
+            .is_none()
+            .then(|| toolchain_file(args))
+            .flatten();
+        let name = match &args.toolchain {
+            Some(_) if !rustup => {
+                return Err(error_other(
+                    "--toolchain needs rustup, which is not installed".to_string(),
+                ))
+            }
+            Some(name) => Some(name.clone()),
+            None if !rustup => None,
+            None => match &file {
+                Some(path) => channel(path)?,
+                None if args.rustc_bootstrap => None,
+                None => Some("nightly".to_string()),
+            },
+        };
+
+        Ok(Self {
+            name,
+            file: file.filter(|_| rustup),
+            bootstrap: args.rustc_bootstrap,
+        })
+    }
+
+    // `program` (cargo or rustc) of the toolchain
+    pub fn command(&self, program: &str) -> Command {
+        let mut cmd = self.name.as_ref().map_or_else(
+            || Command::new(program),
+            |name| {
+                let mut cmd = Command::new("rustup");
+                cmd.arg("run").arg(name).arg(program);
+                cmd
+            },
+        );
+        // lets a stable compiler take -Z flags
+        if self.bootstrap {
+            cmd.env("RUSTC_BOOTSTRAP", "1");
+        }
+
+        cmd
+    }
+
+    // expands an empty crate, which only fails if the compiler does not take -Zunpretty or is
+    // not there at all
+    pub fn check(&self) -> Result<()> {
+        let output = self
+            .command("rustc")
+            .arg("-Zunpretty=expanded")
+            .arg("--crate-type=lib")
+            .arg("-")
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| error_other(format!("Cannot run rustc of {}: {}", self, e)))?;
+
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().next().unwrap_or_default();
+        let fix = if self.bootstrap {
+            "pick one that does with --toolchain"
+        } else {
+            "install nightly (`rustup toolchain install nightly`), pick a nightly toolchain with \
+             --toolchain or let a stable one take -Z flags with --rustc-bootstrap"
+        };
+
+        Err(error_other(format!(
+            "Cannot expand with {}, `rustc -Zunpretty=expanded` fails ({}), {}",
+            self, reason, fix
+        )))
+    }
+}
+
+impl fmt::Display for Toolchain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.name, &self.file) {
+            (Some(name), Some(file)) => {
+                write!(f, "the `{}` toolchain of {}", name, file.display())
+            }
+            (Some(name), None) => write!(f, "the `{}` toolchain", name),
+            (None, Some(file)) => write!(f, "the toolchain of {}", file.display()),
+            (None, None) => f.write_str("the default toolchain"),
+        }
+    }
+}
+
+// rustup reads the toolchain file from the current dir, --manifest-path can point elsewhere
+fn toolchain_file(args: &Args) -> Option<PathBuf> {
+    let dir = match &args.manifest_path {
+        Some(manifest_path) => Path::new(manifest_path)
+            .canonicalize()
+            .ok()?
+            .parent()?
+            .to_path_buf(),
+        None => env::current_dir().ok()?,
+    };
+
+    dir.ancestors()
+        .flat_map(|dir| TOOLCHAIN_FILES.iter().map(move |file| dir.join(file)))
+        .find(|path| path.is_file())
+}
+
+// `[toolchain] channel = "nightly-2022-08-01"`, or just the name in a legacy rust-toolchain.
+// `None` for a file without a channel, like one with a `path`, which rustup picks up itself
+fn channel(path: &Path) -> Result<Option<String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| error_other(format!("Cannot read {}: {}", path.display(), e)))?;
+
+    Ok(parse_channel(&content))
+}
+
+fn parse_channel(content: &str) -> Option<String> {
+    if !content.contains('[') {
+        return Some(content.trim().to_string()).filter(|name| !name.is_empty());
+    }
+
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("channel"))
+        .filter_map(|rest| rest.trim_start().strip_prefix('='))
+        .find_map(toml_string)
+}
+
+// a basic or literal TOML string, or a bare value, without the comment after it
+fn toml_string(value: &str) -> Option<String> {
+    let value = value.trim();
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value.split('#').next()?.trim(),
+    };
+
+    Some(value.to_string()).filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_channel_of_toolchain_files() {
+        assert_eq!(parse_channel("nightly\n").as_deref(), Some("nightly"));
+        assert_eq!(
+            parse_channel("[toolchain]\nchannel = \"nightly-2024-05-01\"\n").as_deref(),
+            Some("nightly-2024-05-01")
+        );
+        assert_eq!(
+            parse_channel("[toolchain]\nchannel = \"nightly-2024-05-01\" # pinned\n").as_deref(),
+            Some("nightly-2024-05-01")
+        );
+        assert_eq!(
+            parse_channel(
+                "[toolchain]\nchannel = 'stable' # literal\ncomponents = [\"rustfmt\"]\n"
+            )
+            .as_deref(),
+            Some("stable")
+        );
+        assert_eq!(parse_channel("[toolchain]\npath = \"/opt/rust\"\n"), None);
+        assert_eq!(parse_channel(""), None);
+    }
+}
@@ -1,73 +1,271 @@
+use crate::metadata::Metadata;
 use crate::{error_other, Context, Event as LookEvent};
 
 use std::io::Result;
+use std::path::{Component, Path, PathBuf};
 use std::thread;
+use std::time::Duration;
 
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError};
 use crossterm::event::{read, Event as CrossTermEvent, KeyCode, KeyEvent, KeyModifiers};
 use hotwatch::{Event, Hotwatch};
+use ignore::{gitignore::Gitignore, WalkBuilder};
 
-pub fn watch(path: &str, ctx: &Context) -> Result<Hotwatch> {
-    let mut hotwatch =
-        Hotwatch::new().map_err(|e| error_other(format!("Cannot Initisalize hotwatch: {}", e)))?;
+// Saving often touches several files (or the same one several times), changes are only
+// reported once nothing else changed for this long
+const DEBOUNCE: Duration = Duration::from_millis(200);
 
-    let ctx = ctx.clone();
+// Decides which changes under a watched path are worth expanding again
+struct Filter {
+    root: PathBuf,
+    // cargo's target dir, where every expansion writes
+    target_dirs: Vec<PathBuf>,
+    gitignores: Vec<Gitignore>,
+}
 
-    hotwatch
-        .watch(path, move |event| {
-            if let Event::Write(_) = event {
-                let _ = ctx.send(LookEvent::FileUpdate);
-            }
-        })
-        .map_err(|e| error_other(format!("Cannot Watch file: {}", e)))?;
+// watch the paths recursively, bursts of changes end up as a single `FileUpdate`
+pub fn watch(paths: &[String], ctx: &Context) -> Result<Hotwatch> {
+    let mut hotwatch = Hotwatch::new_with_custom_delay(Duration::from_millis(50))
+        .map_err(|e| error_other(format!("Cannot Initisalize hotwatch: {}", e)))?;
+
+    let (changes, receiver) = unbounded();
+    debounce(receiver, ctx);
+    // no metadata for a file from --input outside of a package
+    let target_dir = Metadata::load(&ctx.args)
+        .ok()
+        .map(|metadata| metadata.target_directory);
+
+    for path in paths {
+        let filter = Filter::new(Path::new(path), target_dir.clone());
+        let changes = changes.clone();
+
+        hotwatch
+            .watch(path, move |event| {
+                if filter.relevant(&event) {
+                    let _ = changes.send(());
+                }
+            })
+            .map_err(|e| error_other(format!("Cannot Watch {}: {}", path, e)))?;
+    }
 
     Ok(hotwatch)
 }
 
-pub fn watch_events(ctx: &Context) -> Result<()> {
+// the paths that are not inside another one, watching both would report changes twice
+pub fn roots(paths: &[String]) -> Vec<String> {
+    let canonical = |path: &str| Path::new(path).canonicalize().ok();
+    let mut roots: Vec<String> = Vec::new();
+
+    for path in paths {
+        let Some(absolute) = canonical(path) else {
+            // let hotwatch report it
+            roots.push(path.clone());
+            continue;
+        };
+
+        let nested = paths.iter().any(|other| {
+            canonical(other).is_some_and(|other| other != absolute && absolute.starts_with(other))
+        });
+        let duplicate = roots
+            .iter()
+            .any(|root| canonical(root).as_ref() == Some(&absolute));
+        if !nested && !duplicate {
+            roots.push(path.clone());
+        }
+    }
+
+    roots
+}
+
+fn debounce(receiver: Receiver<()>, ctx: &Context) {
     let ctx = ctx.clone();
 
-    thread::spawn(move || loop {
-        match read() {
-            Ok(CrossTermEvent::Key(KeyEvent {
-                code: KeyCode::Char('q'),
-                modifiers: KeyModifiers::NONE,
-            })) => return ctx.send(LookEvent::Interrupt),
-            Ok(CrossTermEvent::Key(KeyEvent {
-                code: KeyCode::Up,
-                modifiers: KeyModifiers::NONE,
-            })) => {
-                ctx.send(LookEvent::KeyArrowUp)?;
-            }
-            Ok(CrossTermEvent::Key(KeyEvent {
-                code: KeyCode::Down,
-                modifiers: KeyModifiers::NONE,
-            })) => {
-                ctx.send(LookEvent::KeyArrowDown)?;
-            }
-            Ok(CrossTermEvent::Key(KeyEvent {
-                code: KeyCode::Right,
-                modifiers: KeyModifiers::NONE,
-            })) => {
-                ctx.send(LookEvent::KeyArrowRight)?;
-            }
-            Ok(CrossTermEvent::Key(KeyEvent {
-                code: KeyCode::Left,
-                modifiers: KeyModifiers::NONE,
-            })) => {
-                ctx.send(LookEvent::KeyArrowLeft)?;
+    thread::spawn(move || -> Result<()> {
+        while receiver.recv().is_ok() {
+            loop {
+                match receiver.recv_timeout(DEBOUNCE) {
+                    Ok(()) => (),
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
             }
-            Ok(CrossTermEvent::Key(KeyEvent {
-                code: KeyCode::Char('r'),
-                modifiers: KeyModifiers::NONE,
-            })) => {
-                ctx.send(LookEvent::FileUpdate)?;
+
+            ctx.send(LookEvent::FileUpdate)?;
+        }
+
+        Ok(())
+    });
+}
+
+impl Filter {
+    // `target_dir` as cargo metadata has it, `target` next to the manifest above the path is
+    // skipped either way
+    fn new(path: &Path, target_dir: Option<PathBuf>) -> Self {
+        let root = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let dir = if root.is_dir() {
+            root.clone()
+        } else {
+            root.parent()
+                .map_or_else(|| root.clone(), Path::to_path_buf)
+        };
+
+        // the .gitignore files of the repository above the path and the ones inside it
+        let repository = dir.ancestors().find(|dir| dir.join(".git").exists());
+        let above = repository.map_or_else(Vec::new, |repository| {
+            dir.ancestors()
+                .skip(1)
+                .take_while(|dir| dir.starts_with(repository))
+                .map(|dir| dir.join(".gitignore"))
+                .collect()
+        });
+        let inside = WalkBuilder::new(&dir)
+            .hidden(false)
+            .build()
+            .filter_map(|entry| entry.ok())
+            .map(ignore::DirEntry::into_path)
+            .filter(|path| path.file_name().is_some_and(|name| name == ".gitignore"));
+
+        let gitignores = above
+            .into_iter()
+            .chain(inside)
+            .filter(|path| path.is_file())
+            .map(|path| Gitignore::new(path).0)
+            .collect();
+
+        let package_target = dir
+            .ancestors()
+            .find(|dir| dir.join("Cargo.toml").is_file())
+            .map(|dir| dir.join("target"));
+        let target_dirs = target_dir.into_iter().chain(package_target).collect();
+
+        Self {
+            root,
+            target_dirs,
+            gitignores,
+        }
+    }
+
+    // writes, creates, removes and renames of files that are not build output or ignored,
+    // editors that save atomically only create or rename
+    fn relevant(&self, event: &Event) -> bool {
+        match event {
+            Event::Create(path) | Event::Write(path) | Event::Remove(path) => self.watched(path),
+            Event::Rename(from, to) => self.watched(from) || self.watched(to),
+            Event::Rescan => true,
+            Event::NoticeWrite(_) | Event::NoticeRemove(_) | Event::Chmod(_) | Event::Error(..) => {
+                false
             }
-            Ok(CrossTermEvent::Resize(_, _)) => {
-                ctx.send(LookEvent::Resize)?;
+        }
+    }
+
+    fn watched(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let build_output = self.target_dirs.iter().any(|dir| path.starts_with(dir))
+            || relative
+                .components()
+                .any(|e| matches!(e, Component::Normal(name) if name == ".git"));
+
+        !build_output
+            && !self.gitignores.iter().any(|gitignore| {
+                path.starts_with(gitignore.path())
+                    && gitignore
+                        .matched_path_or_any_parents(path, path.is_dir())
+                        .is_ignore()
+            })
+    }
+}
+
+pub fn watch_events(ctx: &Context) -> Result<()> {
+    let ctx = ctx.clone();
+
+    thread::spawn(move || -> Result<()> {
+        loop {
+            match read() {
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Up,
+                    modifiers: KeyModifiers::NONE,
+                })) => {
+                    ctx.send(LookEvent::KeyArrowUp)?;
+                }
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Down,
+                    modifiers: KeyModifiers::NONE,
+                })) => {
+                    ctx.send(LookEvent::KeyArrowDown)?;
+                }
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Right,
+                    modifiers: KeyModifiers::NONE,
+                })) => {
+                    ctx.send(LookEvent::KeyArrowRight)?;
+                }
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Left,
+                    modifiers: KeyModifiers::NONE,
+                })) => {
+                    ctx.send(LookEvent::KeyArrowLeft)?;
+                }
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Tab,
+                    modifiers: KeyModifiers::NONE,
+                })) => {
+                    ctx.send(LookEvent::KeyTab)?;
+                }
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Enter,
+                    modifiers: KeyModifiers::NONE,
+                })) => {
+                    ctx.send(LookEvent::KeyEnter)?;
+                }
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Esc,
+                    modifiers: KeyModifiers::NONE,
+                })) => {
+                    ctx.send(LookEvent::KeyEsc)?;
+                }
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Backspace,
+                    modifiers: KeyModifiers::NONE,
+                })) => {
+                    ctx.send(LookEvent::KeyBackspace)?;
+                }
+                // q and r are handled by the receiver since they are also typed into the filter
+                Ok(CrossTermEvent::Key(KeyEvent {
+                    code: KeyCode::Char(c),
+                    modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                })) => {
+                    ctx.send(LookEvent::KeyChar(c))?;
+                }
+                Ok(CrossTermEvent::Resize(_, _)) => {
+                    ctx.send(LookEvent::Resize)?;
+                }
+                _ => (),
             }
-            _ => (),
         }
     });
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs, process};
+
+    #[test]
+    fn only_cargo_target_dir_is_skipped() {
+        let dir = env::temp_dir().join(format!("croc-look-watch-{}", process::id()));
+        fs::create_dir_all(dir.join("src").join("target")).unwrap();
+        fs::write(dir.join("Cargo.toml"), "").unwrap();
+        let root = dir.canonicalize().unwrap();
+        let filter = Filter::new(&root, Some(root.join("build")));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(filter.watched(&root.join("src/target/x86.rs")));
+        assert!(filter.watched(&root.join("src/lib.rs")));
+        assert!(!filter.watched(&root.join("target/debug/build/out.rs")));
+        assert!(!filter.watched(&root.join("build/debug/build/out.rs")));
+        assert!(!filter.watched(&root.join(".git/index")));
+    }
+}
@@ -0,0 +1,102 @@
+use crate::browser::{self, Entry};
+use crate::cmd::{Context, Event};
+use crate::look_or_browse;
+use crate::parse::ParseError;
+
+use std::io::{ErrorKind, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{unbounded, Sender};
+
+// What an expansion in the background found
+pub struct Expansion {
+    pub code: String,
+    pub msg: String,
+    pub entries: Vec<Entry>,
+    pub macro_output: String,
+    // where the items --recover left out do not parse
+    pub skipped: Vec<ParseError>,
+    pub took: Duration,
+}
+
+// Expands on its own thread so the event loop keeps going, a newer request cancels the
+// running expansion and takes its place
+pub struct Worker {
+    ctx: Context,
+    requests: Sender<()>,
+    generation: Arc<AtomicUsize>,
+}
+
+impl Worker {
+    pub fn spawn(ctx: &Context) -> Self {
+        let (requests, receiver) = unbounded();
+        let generation = Arc::new(AtomicUsize::new(0));
+
+        let worker_ctx = ctx.clone();
+        let worker_generation = Arc::clone(&generation);
+        thread::spawn(move || -> Result<()> {
+            while receiver.recv().is_ok() {
+                // requests that queued up meanwhile are covered by this expansion
+                while receiver.try_recv().is_ok() {}
+
+                let current = worker_generation.load(Ordering::SeqCst);
+                worker_ctx.send(Event::ExpansionStarted)?;
+
+                match expand(&worker_ctx) {
+                    // cancelled, the request that did it is queued already
+                    Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                    _ if worker_generation.load(Ordering::SeqCst) != current => (),
+                    result => worker_ctx.send(Event::ExpansionFinished(result))?,
+                }
+            }
+
+            Ok(())
+        });
+
+        // keeps the spinner moving
+        let tick_ctx = ctx.clone();
+        thread::spawn(move || -> Result<()> {
+            loop {
+                thread::sleep(Duration::from_millis(100));
+                tick_ctx.send(Event::Tick)?;
+            }
+        });
+
+        Self {
+            ctx: ctx.clone(),
+            requests,
+            generation,
+        }
+    }
+
+    // expand again, superseding the expansion that is running
+    pub fn request(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.ctx.cancel();
+        let _ = self.requests.send(());
+    }
+
+    // stop the running expansion, nothing is going to wait for it
+    pub fn stop(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.ctx.cancel();
+    }
+}
+
+fn expand(ctx: &Context) -> Result<Expansion> {
+    let now = Instant::now();
+    let (index, macro_output, skipped) = ctx.index()?;
+    let (code, msg) = look_or_browse(ctx, &index)?;
+
+    Ok(Expansion {
+        code,
+        msg,
+        entries: browser::entries(&index),
+        macro_output,
+        skipped,
+        took: now.elapsed(),
+    })
+}